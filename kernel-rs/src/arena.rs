@@ -19,6 +19,10 @@ pub trait Arena: Sized {
     /// Creates handle from condition without increasing reference count.
     fn unforget<C: Fn(&Self::Data) -> bool>(&self, c: C) -> Option<Self::Handle>;
 
+    /// Finds an allocated object satisfying the condition and increases its reference count.
+    /// Unlike find_or_alloc, never allocates.
+    fn find<C: Fn(&Self::Data) -> bool>(&self, c: C) -> Option<Self::Handle>;
+
     /// Find or alloc.
     fn find_or_alloc<C: Fn(&Self::Data) -> bool, N: FnOnce(&mut Self::Data)>(
         &self,
@@ -137,6 +141,22 @@ impl<T: 'static + ArenaObject, const CAPACITY: usize> Arena for Spinlock<ArrayAr
         None
     }
 
+    fn find<C: Fn(&Self::Data) -> bool>(&self, c: C) -> Option<Self::Handle> {
+        let mut this = self.lock();
+
+        for entry in &mut this.entries {
+            if entry.refcnt != 0 && c(&entry.data) {
+                entry.refcnt += 1;
+                return Some(Self::Handle {
+                    ptr: entry,
+                    _marker: PhantomData,
+                });
+            }
+        }
+
+        None
+    }
+
     fn find_or_alloc<C: Fn(&Self::Data) -> bool, N: FnOnce(&mut Self::Data)>(
         &self,
         c: C,
@@ -303,6 +323,28 @@ impl<T: 'static + ArenaObject, const CAPACITY: usize> Arena for Spinlock<MruAren
         None
     }
 
+    fn find<C: Fn(&Self::Data) -> bool>(&self, c: C) -> Option<Self::Handle> {
+        let this = self.lock();
+
+        let mut list_entry = this.head.next();
+        while list_entry as *const _ != &this.head as *const _ {
+            let entry = unsafe {
+                &mut *((list_entry as *const _ as usize - Self::LIST_ENTRY_OFFSET)
+                    as *mut MruEntry<T>)
+            };
+            if entry.refcnt != 0 && c(&entry.data) {
+                entry.refcnt += 1;
+                return Some(Self::Handle {
+                    ptr: entry,
+                    _marker: PhantomData,
+                });
+            }
+            list_entry = list_entry.next();
+        }
+
+        None
+    }
+
     fn find_or_alloc<C: Fn(&Self::Data) -> bool, N: FnOnce(&mut Self::Data)>(
         &self,
         c: C,
@@ -455,6 +497,12 @@ impl<A: Arena, T: Clone + Deref<Target = A>> Arena for T {
         Some(Self::Handle { tag, inner })
     }
 
+    fn find<C: Fn(&Self::Data) -> bool>(&self, c: C) -> Option<Self::Handle> {
+        let tag = self.clone();
+        let inner = ManuallyDrop::new(tag.deref().find(c)?);
+        Some(Self::Handle { tag, inner })
+    }
+
     fn find_or_alloc<C: Fn(&Self::Data) -> bool, N: FnOnce(&mut Self::Data)>(
         &self,
         c: C,
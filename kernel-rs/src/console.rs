@@ -1,7 +1,7 @@
 use crate::{
     file::Devsw,
     kernel::kernel,
-    param::NDEV,
+    param::{KMSGSIZE, NDEV},
     proc::myproc,
     sleepablelock::SleepablelockGuard,
     uart::Uart,
@@ -148,18 +148,40 @@ impl Console {
     }
 }
 
-pub struct Printer {}
+pub struct Printer {
+    /// Ring of the most recent kernel messages, which continue from msgs[nprinted % KMSGSIZE].
+    msgs: [u8; KMSGSIZE],
+
+    /// Number of bytes printed since boot.
+    nprinted: usize,
+}
 
 impl Printer {
     pub const fn new() -> Self {
-        Self {}
+        Self {
+            msgs: [0; KMSGSIZE],
+            nprinted: 0,
+        }
     }
 
     /// putc for Printer.
     /// TODO(@coolofficials): This function should be changed after refactoring Console-Uart-Printer relationship.
     pub fn putc(&mut self, c: i32) {
+        self.msgs[self.nprinted % KMSGSIZE] = c as u8;
+        self.nprinted += 1;
         putc(c);
     }
+
+    /// Return the kept kernel messages as two slices, which are in order
+    /// when concatenated.
+    pub fn msgs(&self) -> (&[u8], &[u8]) {
+        let end = self.nprinted % KMSGSIZE;
+        if self.nprinted < KMSGSIZE {
+            (&self.msgs[..0], &self.msgs[..end])
+        } else {
+            (&self.msgs[end..], &self.msgs[..end])
+        }
+    }
 }
 
 impl fmt::Write for Printer {
@@ -4,10 +4,11 @@ use crate::{
     fs::{InodeGuard, Path},
     kernel::Kernel,
     ok_or,
-    param::MAXARG,
+    param::{ARG_MAX, MAXARG},
     proc::{myproc, proc_freepagetable, proc_pagetable, Proc},
-    riscv::PGSIZE,
-    string::{safestrcpy, strlen},
+    riscv::{pgrounddown, PGSIZE},
+    string::safestrcpy,
+    syscall::fetchstr,
     vm::{KVAddr, PageTable, UVAddr, VAddr},
 };
 use core::{cmp, mem, slice};
@@ -89,9 +90,19 @@ impl ProgHdr {
 }
 
 impl Kernel {
-    pub unsafe fn exec(&self, path: &Path, argv: &[*mut u8]) -> Result<usize, ()> {
+    /// Replace the current process image with the program at path.
+    /// uargv and uenvp are user addresses of null-terminated arrays of string pointers,
+    /// and uenvp may be 0 for an empty environment. The strings are copied onto the
+    /// new user stack one at a time through buf, so they never take more kernel memory.
+    pub unsafe fn exec(
+        &self,
+        path: &Path,
+        uargv: usize,
+        uenvp: usize,
+        buf: &mut [u8],
+    ) -> Result<usize, ()> {
         let sz: usize = 0;
-        let mut ustack = [0usize; MAXARG + 1];
+        let mut ustack = [0usize; 2 * (MAXARG + 1)];
         let mut elf: ElfHdr = Default::default();
         let mut ph: ProgHdr = Default::default();
         let mut p: *mut Proc = myproc();
@@ -169,33 +180,25 @@ impl Kernel {
         let mut sp: usize = *sz;
         let stackbase: usize = sp.wrapping_sub(PGSIZE);
 
-        // Push argument strings, prepare rest of stack in ustack.
-        let mut argc: usize = 0;
-        loop {
-            if argv[argc].is_null() {
-                break;
-            }
-            if argc >= MAXARG {
-                return Err(());
-            }
-            sp = sp.wrapping_sub((strlen(argv[argc]) + 1) as usize);
-
-            // riscv sp must be 16-byte aligned
-            sp = sp.wrapping_sub(sp.wrapping_rem(16));
-            if sp < stackbase {
-                return Err(());
-            }
-            pt.copyout(
-                UVAddr::new(sp),
-                slice::from_raw_parts_mut(argv[argc], (strlen(argv[argc]) + 1) as usize),
-            )?;
-            ustack[argc] = sp;
-            argc = argc.wrapping_add(1)
-        }
+        // Push argument and environment strings, prepare rest of stack in ustack:
+        // argv[0..argc], 0, envp[0..envc], 0.
+        let mut total = 0;
+        let argc = push_strings(pt, &mut sp, stackbase, uargv, buf, &mut ustack, &mut total)?;
         ustack[argc] = 0;
+        let envc = push_strings(
+            pt,
+            &mut sp,
+            stackbase,
+            uenvp,
+            buf,
+            &mut ustack[argc + 1..],
+            &mut total,
+        )?;
+        ustack[argc + 1 + envc] = 0;
+        let nptrs = argc + envc + 2;
 
-        // push the array of argv[] pointers.
-        sp = sp.wrapping_sub(argc.wrapping_add(1).wrapping_mul(mem::size_of::<usize>()));
+        // push the arrays of argv[] and envp[] pointers.
+        sp = sp.wrapping_sub(nptrs.wrapping_mul(mem::size_of::<usize>()));
         sp = sp.wrapping_sub(sp.wrapping_rem(16));
 
         if sp >= stackbase
@@ -204,16 +207,17 @@ impl Kernel {
                     UVAddr::new(sp),
                     slice::from_raw_parts_mut(
                         ustack.as_mut_ptr() as *mut u8,
-                        argc.wrapping_add(1).wrapping_mul(mem::size_of::<usize>()),
+                        nptrs.wrapping_mul(mem::size_of::<usize>()),
                     ),
                 )
                 .is_ok()
         {
             let (pt, sz) = scopeguard::ScopeGuard::into_inner(ptable_guard);
-            // arguments to user main(argc, argv)
+            // arguments to user main(argc, argv, envp)
             // argc is returned via the system call return
             // value, which goes in a0.
             (*data.trapframe).a1 = sp;
+            (*data.trapframe).a2 = sp + (argc + 1) * mem::size_of::<usize>();
 
             // Save program name for debugging.
             let mut s = path.as_bytes().as_ptr();
@@ -248,6 +252,67 @@ impl Kernel {
     }
 }
 
+/// Copy the strings of the null-terminated array at user address uarr, which is
+/// in the current address space, onto the new user stack below sp, and record
+/// their user addresses in ustack. Each string is copied through buf.
+/// total accumulates the sizes of the strings, which may not exceed ARG_MAX.
+/// An array address of 0 is an empty array.
+/// Returns the number of strings.
+unsafe fn push_strings(
+    pt: &mut PageTable<UVAddr>,
+    sp: &mut usize,
+    stackbase: usize,
+    uarr: usize,
+    buf: &mut [u8],
+    ustack: &mut [usize],
+    total: &mut usize,
+) -> Result<usize, ()> {
+    if uarr == 0 {
+        return Ok(0);
+    }
+    let data = &mut *(*myproc()).data.get();
+    let mut uargs = [0usize; 16];
+    let mut n: usize = 0;
+    loop {
+        // Fetch the pointers up to the end of their page at once, so that an array
+        // ending right before an unmapped page is not rejected. Like fetchaddr(),
+        // never read a pointer beyond the process's size.
+        let addr = uarr + mem::size_of::<usize>() * n;
+        let in_size = data.sz.saturating_sub(addr) / mem::size_of::<usize>();
+        if in_size == 0 {
+            return Err(());
+        }
+        let in_page = (pgrounddown(addr) + PGSIZE - addr) / mem::size_of::<usize>();
+        let nfetch = cmp::min(cmp::min(cmp::max(in_page, 1), in_size), uargs.len());
+        let chunk = &mut uargs[..nfetch];
+        data.pagetable.copyin_array(chunk, UVAddr::new(addr))?;
+
+        for &uarg in chunk.iter() {
+            if uarg == 0 {
+                return Ok(n);
+            }
+            if n >= MAXARG {
+                return Err(());
+            }
+            let len = fetchstr(UVAddr::new(uarg), buf)?.to_bytes_with_nul().len();
+            *total += len;
+            if *total > ARG_MAX {
+                return Err(());
+            }
+            *sp = sp.wrapping_sub(len);
+
+            // riscv sp must be 16-byte aligned
+            *sp = sp.wrapping_sub(sp.wrapping_rem(16));
+            if *sp < stackbase {
+                return Err(());
+            }
+            pt.copyout(UVAddr::new(*sp), &buf[..len])?;
+            ustack[n] = *sp;
+            n += 1;
+        }
+    }
+}
+
 /// Load a program segment into pagetable at virtual address va.
 /// va must be page-aligned
 /// and the pages from va to va+sz must already be mapped.
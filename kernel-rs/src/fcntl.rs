@@ -5,5 +5,7 @@ bitflags! {
         const O_RDWR = 0x2;
         const O_CREATE = 0x200;
         const O_TRUNC = 0x400;
+        /// Don't wait for the other end when opening a FIFO.
+        const O_NONBLOCK = 0x800;
     }
 }
@@ -7,9 +7,10 @@ use crate::{
     param::{BSIZE, MAXOPBLOCKS, NFILE},
     pipe::AllocatedPipe,
     proc::{myproc, Proc},
+    resource::RLIMIT_FSIZE,
     spinlock::Spinlock,
     stat::Stat,
-    vm::UVAddr,
+    vm::{KVAddr, UVAddr, VAddr},
 };
 use core::{cell::UnsafeCell, cmp, convert::TryFrom, mem, ops::Deref, slice};
 
@@ -26,6 +27,10 @@ pub enum FileType {
         ip: RcInode<'static>,
         major: u16,
     },
+    Fifo {
+        ip: RcInode<'static>,
+        pipe: AllocatedPipe,
+    },
 }
 
 pub struct File {
@@ -73,7 +78,9 @@ impl File {
         let p: *mut Proc = myproc();
 
         match &self.typ {
-            FileType::Inode { ip, .. } | FileType::Device { ip, .. } => {
+            FileType::Inode { ip, .. }
+            | FileType::Device { ip, .. }
+            | FileType::Fifo { ip, .. } => {
                 let mut st = ip.stat();
                 (*(*p).data.get()).pagetable.copyout(
                     addr,
@@ -95,7 +102,9 @@ impl File {
         }
 
         match &self.typ {
-            FileType::Pipe { pipe } => pipe.read(addr, usize::try_from(n).unwrap_or(0)),
+            FileType::Pipe { pipe } | FileType::Fifo { pipe, .. } => {
+                pipe.read(addr, usize::try_from(n).unwrap_or(0))
+            }
             FileType::Inode { ip, off } => {
                 let tx = kernel().fs().begin_transaction();
                 let ip = ip.deref().lock(&tx);
@@ -123,7 +132,9 @@ impl File {
         }
 
         match &self.typ {
-            FileType::Pipe { pipe } => pipe.write(addr, usize::try_from(n).unwrap_or(0)),
+            FileType::Pipe { pipe } | FileType::Fifo { pipe, .. } => {
+                pipe.write(addr, usize::try_from(n).unwrap_or(0))
+            }
             FileType::Inode { ip, off } => {
                 // write a few blocks at a time to avoid exceeding
                 // the maximum log transaction size, including
@@ -133,12 +144,24 @@ impl File {
                 // might be writing a device like the console.
                 let max = (MAXOPBLOCKS - 1 - 1 - 2) / 2 * BSIZE;
 
+                // The file may not grow past RLIMIT_FSIZE.
+                // Write only the bytes below it, and fail if there are none.
+                let fit = fsize_fit(*off.get(), n as usize);
+                if fit == 0 && n != 0 {
+                    return Err(());
+                }
+                let n = fit;
+
+                if let Some(r) = write_combined(ip, off, addr, n) {
+                    return r;
+                }
+
                 // TODO(@kimjungwow) : To pass copyin() usertest, I reflect the commit on Nov 5, 2020 (below link).
                 // https://github.com/mit-pdos/xv6-riscv/commit/5e392531c07966fd8a6bee50e3e357c553fb2a2f
                 // This comment will be removed as we fetch upstream(mit-pdos)
                 let mut bytes_written: usize = 0;
-                while bytes_written < n as usize {
-                    let bytes_to_write = cmp::min(n as usize - bytes_written, max);
+                while bytes_written < n {
+                    let bytes_to_write = cmp::min(n - bytes_written, max);
                     let tx = kernel().fs().begin_transaction();
                     let mut ip = ip.deref().lock(&tx);
                     let curr_off = *off.get();
@@ -158,10 +181,10 @@ impl File {
                     }
                     bytes_written += r;
                 }
-                if bytes_written != n as usize {
+                if bytes_written != n {
                     return Err(());
                 }
-                Ok(n as usize)
+                Ok(n)
             }
             FileType::Device { major, .. } => kernel()
                 .devsw
@@ -171,6 +194,116 @@ impl File {
             FileType::None => panic!("File::read"),
         }
     }
+
+    /// Is self either end of a pipe or FIFO?
+    pub fn is_pipe(&self) -> bool {
+        matches!(self.typ, FileType::Pipe { .. } | FileType::Fifo { .. })
+    }
+
+    /// Like read(), but into the kernel buffer buf, for splice.
+    /// An inode is read at off, which is advanced, or at the file offset if off is None.
+    /// Devices cannot be read this way.
+    pub unsafe fn read_kernel(&self, buf: &mut [u8], off: Option<&mut u32>) -> Result<usize, ()> {
+        if !self.readable {
+            return Err(());
+        }
+
+        let addr = KVAddr::new(buf.as_mut_ptr() as usize);
+        match &self.typ {
+            FileType::Pipe { pipe } | FileType::Fifo { pipe, .. } => pipe.read(addr, buf.len()),
+            FileType::Inode { ip, off: file_off } => {
+                let off = off.unwrap_or_else(|| &mut *file_off.get());
+                let tx = kernel().fs().begin_transaction();
+                let ip = ip.deref().lock(&tx);
+                let ret = ip.read(addr, *off, buf.len() as u32);
+                if let Ok(v) = ret {
+                    *off = off.wrapping_add(v as u32);
+                }
+                drop(ip);
+                ret
+            }
+            _ => Err(()),
+        }
+    }
+
+    /// Like write(), but from the kernel buffer buf, for splice.
+    /// An inode is written at off, which is advanced, or at the file offset if off is None,
+    /// up to RLIMIT_FSIZE; see write_fit().
+    /// buf must be small enough to be written in a single transaction.
+    /// Devices cannot be written this way.
+    pub unsafe fn write_kernel(&self, buf: &[u8], off: Option<&mut u32>) -> Result<usize, ()> {
+        if !self.writable {
+            return Err(());
+        }
+
+        let addr = KVAddr::new(buf.as_ptr() as usize);
+        match &self.typ {
+            FileType::Pipe { pipe } | FileType::Fifo { pipe, .. } => pipe.write(addr, buf.len()),
+            FileType::Inode { ip, off: file_off } => {
+                let off = off.unwrap_or_else(|| &mut *file_off.get());
+
+                // The file may not grow past RLIMIT_FSIZE.
+                let n = fsize_fit(*off, buf.len());
+                if n == 0 && !buf.is_empty() {
+                    return Err(());
+                }
+
+                let tx = kernel().fs().begin_transaction();
+                let mut ip = ip.deref().lock(&tx);
+                let r = ip.write(addr, *off, n as u32)?;
+                *off = off.wrapping_add(r as u32);
+                drop(ip);
+                if r == 0 && n != 0 {
+                    return Err(());
+                }
+                Ok(r)
+            }
+            _ => Err(()),
+        }
+    }
+
+    /// Return how many of n bytes write_kernel() at off, or at the file offset
+    /// if off is None, may write before the file reaches RLIMIT_FSIZE.
+    pub unsafe fn write_fit(&self, n: usize, off: Option<u32>) -> usize {
+        match &self.typ {
+            FileType::Inode { off: file_off, .. } => fsize_fit(off.unwrap_or(*file_off.get()), n),
+            _ => n,
+        }
+    }
+}
+
+/// Return how many of n bytes written at offset off stay below the
+/// RLIMIT_FSIZE of the current process.
+unsafe fn fsize_fit(off: u32, n: usize) -> usize {
+    let limit = (*(*myproc()).data.get()).rlimits[RLIMIT_FSIZE as usize].cur;
+    cmp::min(n as u64, limit.saturating_sub(off as u64)) as usize
+}
+
+/// Keep a small write of n bytes at the file offset off in the deferred write buffer
+/// of ip; see InodeGuard::write_combined(). If the buffer holds other data, it is
+/// flushed first, in a transaction that logs nothing else.
+/// Returns None if the write is not small, and must be written with InodeGuard::write().
+unsafe fn write_combined(
+    ip: &RcInode<'static>,
+    off: &UnsafeCell<u32>,
+    addr: UVAddr,
+    n: usize,
+) -> Option<Result<usize, ()>> {
+    loop {
+        let tx = kernel().fs().begin_transaction();
+        let mut ip = ip.deref().lock(&tx);
+        let curr_off = *off.get();
+        if !ip.may_combine(curr_off, n as u32) {
+            return None;
+        }
+        if let Some(r) = ip.write_combined(addr, curr_off, n as u32) {
+            if let Ok(v) = r {
+                *off.get() = curr_off.wrapping_add(v as u32);
+            }
+            return Some(r);
+        }
+        ip.flush();
+    }
 }
 
 impl ArenaObject for File {
@@ -179,7 +312,18 @@ impl ArenaObject for File {
             let typ = mem::replace(&mut self.typ, FileType::None);
             match typ {
                 FileType::Pipe { mut pipe } => unsafe { pipe.close(self.writable) },
-                FileType::Inode { ip, .. } | FileType::Device { ip, .. } => {
+                FileType::Inode { ip, .. } => {
+                    // Not in the transaction below, which may also free the file.
+                    unsafe { ip.sync() };
+                    let _tx = kernel().fs().begin_transaction();
+                    drop(ip);
+                }
+                FileType::Device { ip, .. } => {
+                    let _tx = kernel().fs().begin_transaction();
+                    drop(ip);
+                }
+                FileType::Fifo { ip, mut pipe } => {
+                    unsafe { pipe.close_fifo(self.readable, self.writable) };
                     let _tx = kernel().fs().begin_transaction();
                     drop(ip);
                 }
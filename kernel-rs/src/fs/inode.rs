@@ -67,20 +67,24 @@
 //! dev, and inum.  One must hold ip->lock in order to
 //! read or write that inode's ip->valid, ip->size, ip->type, &c.
 
-use core::{mem, ops::Deref, ptr};
+use core::{cmp, mem, ops::Deref, ptr, slice};
 
 use crate::{
     arena::{Arena, ArenaObject, ArrayArena, ArrayEntry, Rc},
     fs::FsTransaction,
     kernel::kernel,
+    ok_or,
     param::{BSIZE, NINODE},
+    pipe::AllocatedPipe,
+    println,
+    riscv::PGSIZE,
     sleeplock::Sleeplock,
     spinlock::Spinlock,
-    stat::{Stat, T_DIR, T_NONE},
+    stat::{mkdev, Stat, T_DEVICE, T_DIR, T_FIFO, T_NONE},
     vm::{KVAddr, VAddr},
 };
 
-use super::{FileName, IPB, MAXFILE, NDIRECT, NINDIRECT};
+use super::{FileName, BPB, IPB, MAXFILE, NDIRECT, NINDIRECT};
 
 /// Directory is a file containing a sequence of Dirent structures.
 pub const DIRSIZ: usize = 14;
@@ -99,6 +103,55 @@ pub struct InodeInner {
     pub size: u32,
     pub addr_direct: [u32; NDIRECT],
     pub addr_indirect: u32,
+    /// pending small writes, not yet in the log
+    wbuf: WriteBuffer,
+    /// pipe of a FIFO (T_FIFO only), allocated on the first open
+    fifo: Option<AllocatedPipe>,
+}
+
+/// Deferred write buffer for contiguous sub-block writes.
+///
+/// Small file writes (see InodeGuard::write_combined) to consecutive offsets
+/// within one block are collected here and written to the log as one block
+/// write, instead of a read-modify-write of the whole block per write.
+/// The buffer is flushed, always in a transaction that logs nothing else, when a small
+/// write does not continue it, when a file referring to the inode is closed, and by
+/// Itable::sync. read() sees the buffered data, and write() logs it together with
+/// the block if it writes the same block.
+/// Until then, the data is not durable, and the size on disk may already cover it;
+/// the block itself is allocated before the first write is buffered.
+struct WriteBuffer {
+    /// file offset of data[0..len]
+    off: u32,
+    len: u32,
+    data: [u8; BSIZE],
+}
+
+impl WriteBuffer {
+    /// If the buffered data belongs to block bn of the file, copy it into data,
+    /// the contents of the block, and empty the buffer. Returns whether it did.
+    fn apply(&mut self, bn: usize, data: &mut [u8; BSIZE]) -> bool {
+        if self.len == 0 || self.off as usize / BSIZE != bn {
+            return false;
+        }
+        let begin = self.off as usize % BSIZE;
+        let len = self.len as usize;
+        data[begin..begin + len].copy_from_slice(&self.data[..len]);
+        self.len = 0;
+        true
+    }
+
+    /// Copy out the buffered bytes among the n bytes at file offset off,
+    /// which were just copied out to dst from their block.
+    unsafe fn copyout<A: VAddr>(&self, dst: A, off: u32, n: u32) -> Result<(), ()> {
+        let lo = cmp::max(off, self.off);
+        let hi = cmp::min(off + n, self.off + self.len);
+        if lo >= hi {
+            return Ok(());
+        }
+        let src = &self.data[(lo - self.off) as usize..(hi - self.off) as usize];
+        VAddr::copyout(dst + (lo - off) as usize, src)
+    }
 }
 
 /// in-memory copy of an inode
@@ -155,12 +208,28 @@ pub struct InodeGuard<'a> {
     tx: &'a FsTransaction<'a>,
 }
 
+/// Iterator over the live entries of a locked directory, in offset order.
+/// Yields each entry and its byte offset, skipping empty slots.
+/// A short read, which means the directory is corrupt, is yielded as an error
+/// and ends the iteration.
+pub struct DirIter<'a, 'b> {
+    dp: &'a mut InodeGuard<'b>,
+    off: u32,
+}
+
 #[derive(Default)]
 pub struct Dirent {
     pub inum: u16,
     name: [u8; DIRSIZ],
 }
 
+impl Dinode {
+    /// Is this on-disk inode unallocated?
+    pub fn is_free(&self) -> bool {
+        self.typ == T_NONE
+    }
+}
+
 impl Dirent {
     /// Fill in name. If name is shorter than DIRSIZ, NUL character is appended as
     /// terminator.
@@ -179,20 +248,24 @@ impl Dirent {
     /// Returns slice which exactly contains `name`.
     ///
     /// It contains no NUL characters.
-    fn get_name(&self) -> &FileName {
+    pub fn get_name(&self) -> &FileName {
         let len = self.name.iter().position(|ch| *ch == 0).unwrap_or(DIRSIZ);
         // Safety: self.name[..len] doesn't contain '\0', and len must be <= DIRSIZ.
         unsafe { FileName::from_bytes(&self.name[..len]) }
     }
 
-    // TODO: Use iterator
-    fn read_entry(&mut self, ip: &mut InodeGuard<'_>, off: u32, panic_msg: &'static str) {
+    /// Read the entry at offset off of the directory ip.
+    /// Fails if the whole entry could not be read.
+    fn read_entry(&mut self, ip: &mut InodeGuard<'_>, off: u32) -> Result<(), ()> {
         let bytes_read = ip.read(
             KVAddr::new(self as *mut Dirent as usize),
             off,
             DIRENT_SIZE as u32,
         );
-        assert_eq!(bytes_read, Ok(DIRENT_SIZE), "{}", panic_msg)
+        if bytes_read != Ok(DIRENT_SIZE) {
+            return Err(());
+        }
+        Ok(())
     }
 }
 
@@ -212,6 +285,16 @@ impl InodeGuard<'_> {
     pub fn deref_inner_mut(&mut self) -> &mut InodeInner {
         unsafe { self.inner.get_mut_unchecked() }
     }
+
+    /// Return the pipe of the FIFO, allocating it if the FIFO isn't open yet.
+    pub unsafe fn fifo_pipe(&mut self) -> Result<AllocatedPipe, ()> {
+        assert_eq!(self.deref_inner().typ, T_FIFO, "fifo_pipe: not a FIFO");
+        let inner = self.deref_inner_mut();
+        if inner.fifo.is_none() {
+            inner.fifo = Some(AllocatedPipe::new(0, 0)?);
+        }
+        Ok(inner.fifo.unwrap())
+    }
 }
 
 /// Unlock and put the given inode.
@@ -223,8 +306,34 @@ impl Drop for InodeGuard<'_> {
     }
 }
 
+impl Iterator for DirIter<'_, '_> {
+    type Item = Result<(u32, Dirent), ()>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.off < self.dp.deref_inner().size {
+            let off = self.off;
+            self.off += DIRENT_SIZE as u32;
+            let mut de: Dirent = Default::default();
+            if de.read_entry(self.dp, off).is_err() {
+                self.off = self.dp.deref_inner().size;
+                return Some(Err(()));
+            }
+            if de.inum != 0 {
+                return Some(Ok((off, de)));
+            }
+        }
+        None
+    }
+}
+
 // Directories
-impl InodeGuard<'_> {
+impl<'b> InodeGuard<'b> {
+    /// Iterate over the entries of the directory dp.
+    pub fn iter_dir(&mut self) -> DirIter<'_, 'b> {
+        assert_eq!(self.deref_inner().typ, T_DIR, "iter_dir not DIR");
+        DirIter { dp: self, off: 0 }
+    }
+
     /// Write a new directory entry (name, inum) into the directory dp.
     pub fn dirlink(&mut self, name: &FileName, inum: u32) -> Result<(), ()> {
         let mut de: Dirent = Default::default();
@@ -237,7 +346,7 @@ impl InodeGuard<'_> {
         // Look for an empty Dirent.
         let mut off: u32 = 0;
         while off < self.deref_inner().size {
-            de.read_entry(self, off, "dirlink read");
+            de.read_entry(self, off)?;
             if de.inum == 0 {
                 break;
             }
@@ -250,22 +359,22 @@ impl InodeGuard<'_> {
             off,
             DIRENT_SIZE as u32,
         );
-        assert_eq!(bytes_write, Ok(DIRENT_SIZE), "dirlink");
+        if bytes_write != Ok(DIRENT_SIZE) {
+            return Err(());
+        }
         Ok(())
     }
 
     /// Look for a directory entry in a directory.
     /// If found, return the entry and byte offset of entry.
+    /// Fails if not found or if the directory is corrupt.
     pub fn dirlookup(&mut self, name: &FileName) -> Result<(RcInode<'static>, u32), ()> {
-        let mut de: Dirent = Default::default();
-
-        assert_eq!(self.deref_inner().typ, T_DIR, "dirlookup not DIR");
-
-        for off in (0..self.deref_inner().size).step_by(DIRENT_SIZE) {
-            de.read_entry(self, off, "dirlookup read");
-            if de.inum != 0 && name == de.get_name() {
+        let dev = self.dev;
+        for entry in self.iter_dir() {
+            let (off, de) = entry?;
+            if name == de.get_name() {
                 // entry matches path element
-                return Ok((kernel().itable.get_inode(self.dev, de.inum as u32), off));
+                return Ok((kernel().itable.get_inode(dev, de.inum as u32), off));
             }
         }
         Err(())
@@ -296,6 +405,7 @@ impl InodeGuard<'_> {
     /// Truncate inode (discard contents).
     /// This function is called with Inode's lock is held.
     pub unsafe fn itrunc(&mut self) {
+        self.deref_inner_mut().wbuf.len = 0;
         let tx = self.tx;
         let dev = self.dev;
         for addr in &mut self.deref_inner_mut().addr_direct {
@@ -344,6 +454,7 @@ impl InodeGuard<'_> {
             let end = begin + m as usize;
             unsafe {
                 VAddr::copyout(dst, &bp.deref_mut_inner().data[begin..end])?;
+                inner.wbuf.copyout(dst, off, m)?;
             }
             tot = tot.wrapping_add(m);
             off = off.wrapping_add(m);
@@ -365,18 +476,26 @@ impl InodeGuard<'_> {
         }
         let mut tot: u32 = 0;
         while tot < n {
-            let mut bp = kernel().disk.read(
-                self.dev,
-                self.bmap_or_alloc((off as usize).wrapping_div(BSIZE)),
-            );
+            let bn = (off as usize).wrapping_div(BSIZE);
+            let mut bp = kernel().disk.read(self.dev, self.bmap_or_alloc(bn));
             let m = core::cmp::min(
                 n.wrapping_sub(tot),
                 (BSIZE as u32).wrapping_sub(off.wrapping_rem(BSIZE as u32)),
             );
             let begin = off.wrapping_rem(BSIZE as u32) as usize;
             let end = begin + m as usize;
+
+            // Buffered small writes to this block are older than this write,
+            // so apply them first, and log them together with it.
+            let merged = self
+                .deref_inner_mut()
+                .wbuf
+                .apply(bn, &mut bp.deref_mut_inner().data);
             unsafe {
                 if VAddr::copyin(&mut bp.deref_mut_inner().data[begin..end], src).is_err() {
+                    if merged {
+                        self.tx.write(bp);
+                    }
                     break;
                 }
             }
@@ -398,12 +517,83 @@ impl InodeGuard<'_> {
         // Write the i-node back to disk even if the size didn't change
         // because the loop above might have called bmap() and added a new
         // block to self->addrs[].
+        // The new size is logged in the same transaction as the data blocks,
+        // so a crash cannot make it durable without them.
         unsafe {
             self.update();
         }
         Ok(tot as usize)
     }
 
+    /// May a write of n bytes at off be kept in the deferred write buffer?
+    /// It must be nonempty, stay within one block, and not leave a hole.
+    pub fn may_combine(&self, off: u32, n: u32) -> bool {
+        n != 0
+            && off as usize % BSIZE + n as usize <= BSIZE
+            && off <= self.deref_inner().size
+            && off as usize + n as usize <= MAXFILE * BSIZE
+    }
+
+    /// Keep a write that may_combine() in the deferred write buffer, instead of writing
+    /// it to the log. The only block that may be logged is a newly allocated one.
+    /// Returns None if the buffer holds data that the write does not continue;
+    /// the caller must then flush() it, in a transaction of its own, and try again.
+    pub fn write_combined<A: VAddr>(
+        &mut self,
+        src: A,
+        off: u32,
+        n: u32,
+    ) -> Option<Result<usize, ()>> {
+        let wbuf = &self.deref_inner().wbuf;
+        if wbuf.len == 0 {
+            // Allocate the block now, so that it exists if the new size reaches the
+            // disk before the data does. A file has no holes, so the block is new
+            // exactly if it starts at the end of the file.
+            if off == self.deref_inner().size && off as usize % BSIZE == 0 {
+                self.bmap_or_alloc(off as usize / BSIZE);
+                unsafe {
+                    self.update();
+                }
+            }
+            self.deref_inner_mut().wbuf.off = off;
+        } else if wbuf.off + wbuf.len != off || wbuf.off as usize / BSIZE != off as usize / BSIZE {
+            return None;
+        }
+
+        let inner = self.deref_inner_mut();
+        let start = (off - inner.wbuf.off) as usize;
+        let r = unsafe { VAddr::copyin(&mut inner.wbuf.data[start..start + n as usize], src) };
+        if r.is_err() {
+            return Some(Err(()));
+        }
+        inner.wbuf.len += n;
+        if off + n > inner.size {
+            inner.size = off + n;
+        }
+        Some(Ok(n as usize))
+    }
+
+    /// Does the deferred write buffer hold data?
+    pub fn has_deferred_writes(&self) -> bool {
+        self.deref_inner().wbuf.len != 0
+    }
+
+    /// Write the deferred write buffer, if it holds data, to its block, and the inode
+    /// with it. This logs two blocks that no system call reserved room for, so the
+    /// transaction must log nothing else.
+    pub unsafe fn flush(&mut self) {
+        if !self.has_deferred_writes() {
+            return;
+        }
+        let bn = self.deref_inner().wbuf.off as usize / BSIZE;
+        let mut bp = kernel().disk.read(self.dev, self.bmap_or_alloc(bn));
+        self.deref_inner_mut()
+            .wbuf
+            .apply(bn, &mut bp.deref_mut_inner().data);
+        self.tx.write(bp);
+        self.update();
+    }
+
     /// Inode content
     ///
     /// The content (data) associated with each inode is stored
@@ -468,21 +658,31 @@ impl InodeGuard<'_> {
         }
     }
 
+    /// May a new link be made to this inode?
+    /// Directories have exactly one name, so they cannot be linked.
+    pub fn may_link(&self) -> bool {
+        self.deref_inner().typ != T_DIR
+    }
+
+    /// May a directory entry referring to this inode be removed?
+    /// A directory must be empty first.
+    /// All syscalls that remove directory entries check this.
+    /// A corrupt directory cannot be removed.
+    pub unsafe fn may_unlink(&mut self) -> bool {
+        self.deref_inner().typ != T_DIR || self.isdirempty() == Ok(true)
+    }
+
     /// Is the directory dp empty except for "." and ".." ?
-    pub unsafe fn isdirempty(&mut self) -> bool {
-        let mut de: Dirent = Default::default();
-        for off in (2 * DIRENT_SIZE as u32..self.deref_inner().size).step_by(DIRENT_SIZE) {
-            let bytes_read = self.read(
-                KVAddr::new(&mut de as *mut Dirent as usize),
-                off as u32,
-                DIRENT_SIZE as u32,
-            );
-            assert_eq!(bytes_read, Ok(DIRENT_SIZE), "isdirempty: readi");
-            if de.inum != 0 {
-                return false;
+    /// Fails if the directory is corrupt.
+    pub unsafe fn isdirempty(&mut self) -> Result<bool, ()> {
+        for entry in self.iter_dir() {
+            let (off, _) = entry?;
+            // The first two entries are "." and "..".
+            if off >= 2 * DIRENT_SIZE as u32 {
+                return Ok(false);
             }
         }
-        true
+        Ok(true)
     }
 }
 
@@ -496,6 +696,11 @@ impl ArenaObject for Inode {
     /// case it has to free the inode.
     #[allow(clippy::cast_ref_to_mut)]
     fn finalize<'s, A: Arena>(&'s mut self, guard: &'s mut A::Guard<'_>) {
+        // No file has the FIFO open anymore.
+        if let Some(pipe) = self.inner.get_mut().fifo.take() {
+            unsafe { pipe.free() };
+        }
+
         if self.inner.get_mut().valid && self.inner.get_mut().nlink == 0 {
             // inode has no links and no other references: truncate and free.
 
@@ -560,11 +765,23 @@ impl Inode {
                     size: 0,
                     addr_direct: [0; NDIRECT],
                     addr_indirect: 0,
+                    wbuf: WriteBuffer {
+                        off: 0,
+                        len: 0,
+                        data: [0; BSIZE],
+                    },
+                    fifo: None,
                 },
             ),
         }
     }
 
+    /// Flush the deferred write buffer, in a transaction of its own.
+    pub unsafe fn sync(&self) {
+        let tx = kernel().fs().begin_transaction();
+        self.lock(&tx).flush();
+    }
+
     /// Copy stat information from inode.
     pub fn stat(&self) -> Stat {
         let inner = self.inner.lock();
@@ -573,6 +790,11 @@ impl Inode {
             ino: self.inum,
             typ: inner.typ,
             nlink: inner.nlink,
+            rdev: if inner.typ == T_DEVICE {
+                mkdev(inner.major, inner.minor)
+            } else {
+                0
+            },
             size: inner.size as usize,
         }
     }
@@ -632,4 +854,147 @@ impl Itable {
         }
         panic!("[Itable::alloc_inode] no inodes");
     }
+
+    /// Write the deferred writes of every in-memory inode on device dev
+    /// to the disk, e.g. before shutting down the machine.
+    pub unsafe fn sync(&self, dev: u32) {
+        for inum in 1..kernel().fs().superblock.ninodes {
+            // Does not allocate, so inodes that are not in memory are never read from disk.
+            if let Some(inner) = self.find(|inode| inode.dev == dev && inode.inum == inum) {
+                let ip = Rc::from_unchecked(self, inner);
+                ip.sync();
+                let _tx = kernel().fs().begin_transaction();
+                drop(ip);
+            }
+        }
+    }
+
+    /// Check the file system on device dev, and print each inconsistency found:
+    /// an inode whose nlink differs from the number of directory entries naming it,
+    /// a block claimed twice or out of the data area, and a block whose bit in the
+    /// free map disagrees with whether some inode claims it.
+    /// Returns the number of inconsistencies.
+    /// Other processes should not change the file system meanwhile.
+    pub unsafe fn fsck(&self, dev: u32) -> Result<usize, ()> {
+        let sb = kernel().fs().superblock;
+        let (ninodes, size) = (sb.ninodes as usize, sb.size as usize);
+        // The blocks before the data area are always in use.
+        let datastart = sb.bmapstart + sb.size / BPB + 1;
+
+        // Number of directory entries naming each inode, and a bitmap of claimed blocks.
+        if ninodes * mem::size_of::<u16>() + size / 8 + 1 > PGSIZE {
+            return Err(());
+        }
+        let mut page = kernel().alloc().ok_or(())?;
+        page.write_bytes(0);
+        let (head, claimed) = page.split_at_mut(ninodes * mem::size_of::<u16>());
+        let links = slice::from_raw_parts_mut(head.as_mut_ptr() as *mut u16, ninodes);
+        let mut errors = 0;
+
+        self.sync(dev);
+        let tx = kernel().fs().begin_transaction();
+        let read_dinode = |inum: u32| {
+            let bp = kernel().disk.read(dev, sb.iblock(inum));
+            ptr::read((bp.deref_inner().data.as_ptr() as *const Dinode).add(inum as usize % IPB))
+        };
+
+        for inum in 1..sb.ninodes {
+            let dip = read_dinode(inum);
+            if dip.typ == T_NONE {
+                continue;
+            }
+
+            let mut claim = |b: u32| {
+                if b < datastart || b >= sb.size {
+                    println!(
+                        "fsck: inode {} claims block {} outside the data area",
+                        inum, b
+                    );
+                    errors += 1;
+                    false
+                } else if claimed[b as usize / 8] & (1 << (b % 8)) != 0 {
+                    println!("fsck: inode {} claims block {}, already claimed", inum, b);
+                    errors += 1;
+                    false
+                } else {
+                    claimed[b as usize / 8] |= 1 << (b % 8);
+                    true
+                }
+            };
+            for &b in dip.addr_direct.iter().filter(|b| **b != 0) {
+                claim(b);
+            }
+            if dip.addr_indirect != 0 && claim(dip.addr_indirect) {
+                let bp = kernel().disk.read(dev, dip.addr_indirect);
+                let a = bp.deref_inner().data.as_ptr() as *const u32;
+                for j in 0..NINDIRECT {
+                    if *a.add(j) != 0 {
+                        claim(*a.add(j));
+                    }
+                }
+            }
+
+            if dip.typ == T_DIR {
+                let ip = self.get_inode(dev, inum);
+                let mut guard = ip.lock(&tx);
+                for entry in guard.iter_dir() {
+                    let (_, de) = ok_or!(entry, {
+                        println!("fsck: directory {} is corrupt", inum);
+                        errors += 1;
+                        break;
+                    });
+                    // "." doesn't count as a link; see create().
+                    if de.get_name().as_bytes() == b"." {
+                        continue;
+                    }
+                    match links.get_mut(de.inum as usize) {
+                        Some(n) => *n += 1,
+                        None => {
+                            println!("fsck: directory {} names bad inode {}", inum, de.inum);
+                            errors += 1;
+                        }
+                    }
+                }
+                drop(guard);
+                drop(ip);
+            }
+        }
+
+        for inum in 1..sb.ninodes {
+            let dip = read_dinode(inum);
+            let n = links[inum as usize];
+            if dip.typ == T_NONE && n != 0 {
+                println!("fsck: free inode {} is named by {} entries", inum, n);
+                errors += 1;
+            } else if dip.typ != T_NONE && dip.nlink as i32 != n as i32 {
+                println!(
+                    "fsck: inode {} has nlink {} but {} entries",
+                    inum, dip.nlink, n
+                );
+                errors += 1;
+            }
+        }
+        drop(tx);
+
+        for start in num_iter::range_step(0, sb.size, BPB) {
+            let bp = kernel().disk.read(dev, sb.bblock(start));
+            for bi in 0..cmp::min(BPB, sb.size - start) {
+                let b = start + bi;
+                let used = bp.deref_inner().data[bi as usize / 8] & (1 << (bi % 8)) != 0;
+                let wanted = b < datastart || claimed[b as usize / 8] & (1 << (b % 8)) != 0;
+                if used != wanted {
+                    println!(
+                        "fsck: block {} is {} in the free map but {}",
+                        b,
+                        if used { "used" } else { "free" },
+                        if wanted { "claimed" } else { "unclaimed" }
+                    );
+                    errors += 1;
+                }
+            }
+        }
+
+        kernel().free(page);
+        Ok(errors)
+    }
 }
@@ -13,7 +13,13 @@
 
 use core::{cmp, mem, ptr};
 
-use crate::{bio::Buf, kernel::kernel, param::BSIZE, sleepablelock::Sleepablelock, stat::T_DIR};
+use crate::{
+    bio::Buf,
+    kernel::kernel,
+    param::BSIZE,
+    sleepablelock::Sleepablelock,
+    stat::{Statfs, T_DIR},
+};
 
 mod inode;
 mod log;
@@ -21,7 +27,7 @@ mod path;
 mod superblock;
 
 pub use inode::{
-    Dinode, Dirent, Inode, InodeGuard, InodeInner, Itable, RcInode, DIRENT_SIZE, DIRSIZ,
+    Dinode, DirIter, Dirent, Inode, InodeGuard, InodeInner, Itable, RcInode, DIRENT_SIZE, DIRSIZ,
 };
 pub use log::Log;
 pub use path::{FileName, Path};
@@ -65,6 +71,42 @@ impl FileSystem {
         }
         FsTransaction { fs: self }
     }
+
+    /// Report block and inode usage of the file system.
+    /// Counts free blocks in the free bit map and free inodes in the inode blocks.
+    pub fn statfs(&self, dev: u32) -> Statfs {
+        let sb = &self.superblock;
+        let mut bfree = 0;
+        for b in num_iter::range_step(0, sb.size, BPB) {
+            let bp = kernel().disk.read(dev, sb.bblock(b));
+            for bi in 0..cmp::min(BPB, sb.size - b) {
+                if bp.deref_inner().data[(bi / 8) as usize] & (1 << (bi % 8)) == 0 {
+                    bfree += 1;
+                }
+            }
+        }
+
+        // Inode 0 is never used.
+        let mut ffree = 0;
+        for inum in 1..sb.ninodes {
+            let bp = kernel().disk.read(dev, sb.iblock(inum));
+            let dip = unsafe {
+                &*(bp.deref_inner().data.as_ptr() as *const Dinode)
+                    .add((inum as usize).wrapping_rem(IPB))
+            };
+            if dip.is_free() {
+                ffree += 1;
+            }
+        }
+
+        Statfs {
+            bsize: BSIZE as u32,
+            blocks: sb.nblocks,
+            bfree,
+            files: sb.ninodes - 1,
+            ffree,
+        }
+    }
 }
 
 impl Drop for FsTransaction<'_> {
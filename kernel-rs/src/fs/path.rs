@@ -1,4 +1,3 @@
-use core::cmp;
 use core::mem;
 use cstr_core::CStr;
 
@@ -16,19 +15,26 @@ pub struct FileName {
 }
 
 impl FileName {
-    /// Truncate bytes followed by the first DIRSIZ bytes.
+    /// Make a FileName out of `bytes`. Nothing is truncated: callers must first
+    /// check that the name is at most DIRSIZ bytes, and reject it otherwise.
     ///
     /// # Safety
     ///
-    /// `bytes` must not contain any NUL characters.
+    /// `bytes` must not contain any NUL characters, and must not be longer than DIRSIZ.
     pub unsafe fn from_bytes(bytes: &[u8]) -> &Self {
         debug_assert!(!bytes.contains(&0));
-        &*(&bytes[..cmp::min(DIRSIZ, bytes.len())] as *const [u8] as *const Self)
+        debug_assert!(bytes.len() <= DIRSIZ);
+        &*(bytes as *const [u8] as *const Self)
     }
 
     pub fn as_bytes(&self) -> &[u8] {
         &self.inner
     }
+
+    /// Is this "." or ".."? Such entries can never be linked or unlinked.
+    pub fn is_dot_or_dotdot(&self) -> bool {
+        self.as_bytes() == b"." || self.as_bytes() == b".."
+    }
 }
 
 #[repr(transparent)]
@@ -75,7 +81,7 @@ impl Path {
     }
 
     /// Returns `Some((path, name))` where,
-    ///  - `name` is the next path element from `self`, and
+    ///  - `name` is the next path element from `self`, which may be longer than DIRSIZ, and
     ///  - `path` is the remaining path.
     ///
     /// The returned path has no leading slashes, so the caller can check path.inner.is_empty() to
@@ -104,7 +110,7 @@ impl Path {
     /// ```
     // TODO: Make an iterator.
     // TODO: Fix doctests work.
-    fn skipelem(&self) -> Option<(&Self, &FileName)> {
+    fn skipelem(&self) -> Option<(&Self, &[u8])> {
         let mut bytes = &self.inner;
 
         let name_start = bytes.iter().position(|ch| *ch != b'/')?;
@@ -115,7 +121,7 @@ impl Path {
             .position(|ch| *ch == b'/')
             .unwrap_or(bytes.len());
 
-        let name = unsafe { FileName::from_bytes(&bytes[..len]) };
+        let name = &bytes[..len];
 
         bytes = &bytes[len..];
 
@@ -137,16 +143,19 @@ impl Path {
     /// Look up and return the inode for a path name.
     /// If parent != 0, return the inode for the parent and copy the final
     /// path element into name, which must have room for DIRSIZ bytes.
+    /// Fails if a path element is longer than DIRSIZ.
     /// Must be called inside a transaction since it calls Inode::put().
     unsafe fn namex(
         &self,
         parent: bool,
         tx: &FsTransaction<'_>,
     ) -> Result<(RcInode<'static>, Option<&FileName>), ()> {
+        let data = &*(*myproc()).data.get();
+        let root = data.root.as_ref().unwrap();
         let mut ptr = if self.is_absolute() {
-            Self::root()
+            root.clone()
         } else {
-            (*(*myproc()).data.get()).cwd.clone().unwrap()
+            data.cwd.clone().unwrap()
         };
 
         let mut path = self;
@@ -154,6 +163,15 @@ impl Path {
         while let Some((new_path, name)) = path.skipelem() {
             path = new_path;
 
+            // Reject a name that would be truncated to DIRSIZ,
+            // so that two different names never refer to the same entry.
+            if name.len() > DIRSIZ {
+                return Err(());
+            }
+            // SAFETY: `name` is a subslice of `self.inner`, which contains no NUL characters,
+            // and is not longer than DIRSIZ.
+            let name = FileName::from_bytes(name);
+
             let mut ip = ptr.lock(tx);
             if ip.deref_inner().typ != T_DIR {
                 return Err(());
@@ -163,6 +181,10 @@ impl Path {
                 mem::drop(ip);
                 return Ok((ptr, Some(name)));
             }
+            if name.as_bytes() == b".." && ptr.dev == root.dev && ptr.inum == root.inum {
+                // `..` of the process's root is the root itself.
+                continue;
+            }
             let next = ip.dirlookup(name);
             mem::drop(ip);
             ptr = next?.0
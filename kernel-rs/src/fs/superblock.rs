@@ -21,7 +21,7 @@ pub struct Superblock {
     pub size: u32,
 
     /// Number of data blocks
-    nblocks: u32,
+    pub nblocks: u32,
 
     /// Number of inodes
     pub ninodes: u32,
@@ -1,5 +1,5 @@
 use core::fmt::{self, Write};
-use core::sync::atomic::{spin_loop_hint, AtomicBool, Ordering};
+use core::sync::atomic::{spin_loop_hint, AtomicBool, AtomicU32, AtomicU64, Ordering};
 use spin::Once;
 
 use crate::{
@@ -17,9 +17,10 @@ use crate::{
     riscv::PGSIZE,
     sleepablelock::Sleepablelock,
     spinlock::Spinlock,
+    timer::TimerWheel,
     trap::{trapinit, trapinithart},
     uart::Uart,
-    virtio_disk::{virtio_disk_init, Disk},
+    virtio_disk::{diskidinit, disktraceinit, virtio_disk_init, Disk},
     vm::{KVAddr, PageTable},
 };
 
@@ -36,6 +37,9 @@ pub fn kernel() -> &'static Kernel {
 pub struct Kernel {
     panicked: AtomicBool,
 
+    /// One bit per CPU id, set once the CPU has started scheduling.
+    online_cpus: AtomicU64,
+
     /// Sleeps waiting for there are some input in console buffer.
     pub console: Sleepablelock<Console>,
 
@@ -50,7 +54,11 @@ pub struct Kernel {
     /// The kernel's page table.
     pub page_table: PageTable<KVAddr>,
 
-    pub ticks: Sleepablelock<u32>,
+    pub ticks: Sleepablelock<TimerWheel>,
+
+    /// Copy of ticks.ticks() that can be read without taking the lock, e.g. by
+    /// an interrupt handler that already holds another lock.
+    ticks_snapshot: AtomicU32,
 
     /// Current process system.
     pub procs: ProcessSystem,
@@ -82,12 +90,14 @@ impl Kernel {
     const fn zero() -> Self {
         Self {
             panicked: AtomicBool::new(false),
+            online_cpus: AtomicU64::new(0),
             console: Sleepablelock::new("CONS", Console::new()),
             uart: Uart::new(),
             printer: Spinlock::new("PRINTLN", Printer::new()),
             kmem: Spinlock::new("KMEM", Kmem::new()),
             page_table: PageTable::zero(),
-            ticks: Sleepablelock::new("time", 0),
+            ticks: Sleepablelock::new("time", TimerWheel::zero()),
+            ticks_snapshot: AtomicU32::new(0),
             procs: ProcessSystem::zero(),
             cpus: [Cpu::new(); NCPU],
             bcache: Bcache::zero(),
@@ -111,6 +121,24 @@ impl Kernel {
         self.panicked.load(Ordering::Acquire)
     }
 
+    /// CPUs that have started scheduling, one bit per CPU id.
+    pub fn online_cpus(&self) -> u64 {
+        self.online_cpus.load(Ordering::Acquire)
+    }
+
+    /// Advance the clock by one tick. Called by the clock interrupt.
+    pub fn tick(&self) {
+        let mut ticks = self.ticks.lock();
+        ticks.tick();
+        self.ticks_snapshot.store(ticks.ticks(), Ordering::Release);
+    }
+
+    /// The current tick, read without taking the ticks lock.
+    /// It is updated after the tick's timers fire, so it may briefly lag behind ticks.ticks().
+    pub fn ticks_snapshot(&self) -> u32 {
+        self.ticks_snapshot.load(Ordering::Acquire)
+    }
+
     /// Free the page of physical memory pointed at by v,
     /// which normally should have been returned by a
     /// call to kernel().alloc().  (The exception is when
@@ -212,6 +240,15 @@ pub unsafe fn kernel_main() -> ! {
         println!("rv6 kernel is booting");
         println!();
 
+        // Kernel self-tests.
+        #[cfg(feature = "test")]
+        {
+            crate::list::list_selftest();
+            crate::list::list_swap_selftest();
+            crate::timer::timer_selftest();
+            crate::trap::devintr_selftest();
+        }
+
         // Physical page allocator.
         kinit(KERNEL.kmem.get_mut());
 
@@ -236,11 +273,18 @@ pub unsafe fn kernel_main() -> ! {
         // Ask PLIC for device interrupts.
         plicinithart();
 
+        // Timer wheel.
+        KERNEL.ticks.get_mut().init();
+
         // Buffer cache.
         KERNEL.bcache.get_mut().init();
 
         // Emulated hard disk.
         virtio_disk_init(&mut KERNEL.virtqueue, KERNEL.disk.get_mut());
+        #[cfg(feature = "test")]
+        KERNEL.disk.get_mut().notify_selftest();
+        disktraceinit(&mut KERNEL.devsw);
+        diskidinit(&mut KERNEL.devsw);
 
         // First user process.
         KERNEL.procs.user_proc_init();
@@ -262,5 +306,6 @@ pub unsafe fn kernel_main() -> ! {
         plicinithart();
     }
 
+    KERNEL.online_cpus.fetch_or(1 << cpuid(), Ordering::Release);
     scheduler()
 }
@@ -30,12 +30,14 @@ mod kalloc;
 mod kernel;
 mod list;
 mod memlayout;
+mod mman;
 mod page;
 mod param;
 mod pipe;
 mod plic;
 mod poweroff;
 mod proc;
+mod resource;
 mod riscv;
 mod sleepablelock;
 mod sleeplock;
@@ -46,6 +48,7 @@ mod string;
 mod syscall;
 mod sysfile;
 mod sysproc;
+mod timer;
 mod trap;
 mod uart;
 mod utils;
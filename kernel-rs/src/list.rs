@@ -2,7 +2,7 @@
 //! `ListEntry` types must be first initialized with init()
 //! before calling its member functions.
 
-use core::ptr;
+use core::{mem, ptr};
 
 pub struct ListEntry {
     next: *mut ListEntry,
@@ -39,6 +39,9 @@ impl ListEntry {
             (*e.next).prev = e;
             (*e.prev).next = e;
         }
+
+        #[cfg(debug_assertions)]
+        e.check_links();
     }
 
     /// `this` <-> `e`
@@ -50,6 +53,9 @@ impl ListEntry {
             (*e.next).prev = e;
             (*e.prev).next = e;
         }
+
+        #[cfg(debug_assertions)]
+        e.check_links();
     }
 
     pub fn is_empty(&self) -> bool {
@@ -57,6 +63,9 @@ impl ListEntry {
     }
 
     pub fn remove(&mut self) {
+        #[cfg(debug_assertions)]
+        self.check_links();
+
         unsafe {
             (*self.prev).next = self.next;
             (*self.next).prev = self.prev;
@@ -64,9 +73,152 @@ impl ListEntry {
         self.init();
     }
 
+    /// Exchange the entries of the lists headed by `self` and `other` in O(1).
+    /// Both must be list heads, not entries of the same list.
+    pub fn swap(&mut self, other: &mut ListEntry) {
+        let self_empty = self.is_empty();
+        let other_empty = other.is_empty();
+
+        mem::swap(&mut self.next, &mut other.next);
+        mem::swap(&mut self.prev, &mut other.prev);
+
+        self.reanchor(other_empty);
+        other.reanchor(self_empty);
+
+        #[cfg(debug_assertions)]
+        {
+            self.check_links();
+            other.check_links();
+        }
+    }
+
+    /// Make the first and last entries point back to `self` as their head.
+    /// If the list is `empty`, `self` must point to itself instead.
+    fn reanchor(&mut self, empty: bool) {
+        if empty {
+            self.init();
+        } else {
+            unsafe {
+                (*self.next).prev = self;
+                (*self.prev).next = self;
+            }
+        }
+    }
+
     pub fn list_pop_front(&self) -> &ListEntry {
         let result = unsafe { &mut *self.next };
         result.remove();
         result
     }
+
+    /// Check that `self` and its two neighbours point at each other, and panic otherwise.
+    /// This is O(1), so append, prepend and remove run it in debug builds.
+    #[cfg(debug_assertions)]
+    fn check_links(&self) {
+        assert!(
+            !self.next.is_null() && !self.prev.is_null(),
+            "ListEntry: not initialized"
+        );
+        assert!(
+            unsafe { (*self.next).prev } as *const _ == self as *const _
+                && unsafe { (*self.prev).next } as *const _ == self as *const _,
+            "ListEntry: next and prev are inconsistent"
+        );
+    }
+
+    /// Check that the whole list containing `self` is well formed, and panic otherwise.
+    /// This walks the list, so it is not run by the other operations; call it
+    /// when looking for a corruption.
+    #[cfg(any(debug_assertions, feature = "test"))]
+    pub fn check_invariants(&self) {
+        if let Err(msg) = self.find_corruption() {
+            panic!("ListEntry: {}", msg);
+        }
+    }
+
+    /// Walk the list containing `self` forward, checking `e.next.prev == e` for every
+    /// entry `e`, and then backward, checking `e.prev.next == e`. Both walks must close
+    /// on `self`.
+    #[cfg(any(debug_assertions, feature = "test"))]
+    fn find_corruption(&self) -> Result<(), &'static str> {
+        let mut e = self as *const ListEntry;
+        loop {
+            let next = unsafe { (*e).next };
+            if next.is_null() {
+                return Err("not initialized");
+            }
+            if unsafe { (*next).prev } as *const _ != e {
+                return Err("next and prev are inconsistent");
+            }
+            e = next;
+            if e == self as *const _ {
+                break;
+            }
+        }
+        loop {
+            let prev = unsafe { (*e).prev };
+            if unsafe { (*prev).next } as *const _ != e {
+                return Err("prev and next are inconsistent");
+            }
+            e = prev;
+            if e == self as *const _ {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Check the list invariant checker on a well-formed list and on one whose `prev`
+/// pointer was corrupted. Run at boot when the kernel is built with the `test` feature.
+#[cfg(feature = "test")]
+pub fn list_selftest() {
+    let mut head = ListEntry::new();
+    let mut entries = [ListEntry::new(), ListEntry::new(), ListEntry::new()];
+    head.init();
+    for e in &mut entries {
+        head.append(e);
+    }
+    assert!(head.find_corruption().is_ok());
+    assert!(entries[1].find_corruption().is_ok());
+
+    entries[1].prev = &mut entries[2];
+    assert!(head.find_corruption().is_err());
+    entries[1].prev = &mut entries[0];
+    assert!(head.find_corruption().is_ok());
+}
+
+/// Swap a 3-entry list with a 2-entry list and then with an empty list, checking that
+/// each head ends up with the other's entries in order. Run at boot with the `test` feature.
+#[cfg(feature = "test")]
+pub fn list_swap_selftest() {
+    fn assert_entries(head: &ListEntry, entries: &[ListEntry]) {
+        head.check_invariants();
+        let mut e = head.next();
+        for expected in entries {
+            assert!(e as *const _ == expected as *const _);
+            e = e.next();
+        }
+        assert!(e as *const _ == head as *const _);
+    }
+
+    let (mut a, mut b, mut c) = (ListEntry::new(), ListEntry::new(), ListEntry::new());
+    let mut a_entries = [ListEntry::new(), ListEntry::new(), ListEntry::new()];
+    let mut b_entries = [ListEntry::new(), ListEntry::new()];
+    a.init();
+    b.init();
+    c.init();
+    for e in &mut a_entries {
+        a.append(e);
+    }
+    for e in &mut b_entries {
+        b.append(e);
+    }
+
+    a.swap(&mut b);
+    assert_entries(&a, &b_entries);
+    assert_entries(&b, &a_entries);
+
+    a.swap(&mut c);
+    assert_entries(&a, &[]);
+    assert_entries(&c, &b_entries);
 }
@@ -0,0 +1,10 @@
+//! Memory protection flags for mprotect().
+
+/// Pages may be read.
+pub const PROT_READ: i32 = 0x1;
+
+/// Pages may be written.
+pub const PROT_WRITE: i32 = 0x2;
+
+/// Pages may be executed.
+pub const PROT_EXEC: i32 = 0x4;
@@ -4,9 +4,12 @@ pub const NPROC: usize = 64;
 /// Maximum number of CPUs.
 pub const NCPU: usize = 8;
 
-/// Open files per process.
+/// Open files per process kept inline in the process.
 pub const NOFILE: usize = 16;
 
+/// Maximum open files per process, including the overflow table.
+pub const NOFILE_MAX: usize = 64;
+
 /// Open files per system.
 pub const NFILE: usize = 100;
 
@@ -22,6 +25,10 @@ pub const ROOTDEV: u32 = 1;
 /// Max exec arguments.
 pub const MAXARG: usize = 32;
 
+/// Max total bytes of exec argument strings, including their nuls.
+/// They must fit in the one-page user stack.
+pub const ARG_MAX: usize = 4096;
+
 /// Block Size.
 pub const BSIZE: usize = 1024;
 
@@ -41,5 +48,8 @@ pub const FSSIZE: usize = 1000;
 /// Maximum file path name.
 pub const MAXPATH: usize = 128;
 
+/// Bytes of the most recent kernel messages kept for dmesg.
+pub const KMSGSIZE: usize = 4096;
+
 /// Maximum length of process name.
 pub const MAXPROCNAME: usize = 16;
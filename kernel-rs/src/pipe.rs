@@ -4,7 +4,7 @@ use crate::{
     page::Page,
     proc::{myproc, WaitChannel},
     spinlock::Spinlock,
-    vm::UVAddr,
+    vm::VAddr,
 };
 use core::ops::Deref;
 
@@ -19,11 +19,16 @@ struct PipeInner {
     /// Number of bytes written.
     nwrite: u32,
 
-    /// Read fd is still open.
-    readopen: bool,
+    /// Number of open files reading from the pipe.
+    readers: u32,
 
-    /// Write fd is still open.
-    writeopen: bool,
+    /// Number of open files writing to the pipe.
+    writers: u32,
+
+    /// Number of times each end has been opened, so that Pipe::open_fifo() notices
+    /// the other end being opened even if it is closed again before we wake up.
+    nreadopen: u32,
+    nwriteopen: u32,
 }
 
 pub struct Pipe {
@@ -40,7 +45,7 @@ impl Pipe {
     /// PipeInner::try_read() tries to read as much as possible.
     /// Pipe::read() executes try_read() until all bytes in pipe are read.
     //TODO : `n` should be u32
-    pub unsafe fn read(&self, addr: UVAddr, n: usize) -> Result<usize, ()> {
+    pub unsafe fn read<A: VAddr>(&self, addr: A, n: usize) -> Result<usize, ()> {
         let mut inner = self.inner.lock();
         loop {
             match inner.try_read(addr, n) {
@@ -60,7 +65,7 @@ impl Pipe {
 
     /// PipeInner::try_write() tries to write as much as possible.
     /// Pipe::write() executes try_write() until `n` bytes are written.
-    pub unsafe fn write(&self, addr: UVAddr, n: usize) -> Result<usize, ()> {
+    pub unsafe fn write<A: VAddr>(&self, addr: A, n: usize) -> Result<usize, ()> {
         let mut written = 0;
         let mut inner = self.inner.lock();
         loop {
@@ -83,19 +88,73 @@ impl Pipe {
         }
     }
 
-    unsafe fn close(&self, writable: bool) -> bool {
+    /// Open the ends of a FIFO a file reads from or writes to.
+    /// A file opened only for reading (writing) waits until the FIFO has a writer (reader).
+    /// If `nonblock`, opening only for reading doesn't wait, and opening only for writing
+    /// fails if there is no reader.
+    /// On error, the ends are still counted as open and must be closed by the caller.
+    pub unsafe fn open_fifo(
+        &self,
+        readable: bool,
+        writable: bool,
+        nonblock: bool,
+    ) -> Result<(), ()> {
+        let mut inner = self.inner.lock();
+        if readable {
+            inner.readers += 1;
+            inner.nreadopen = inner.nreadopen.wrapping_add(1);
+            self.write_waitchannel.wakeup();
+        }
+        if writable {
+            inner.writers += 1;
+            inner.nwriteopen = inner.nwriteopen.wrapping_add(1);
+            self.read_waitchannel.wakeup();
+        }
+
+        if nonblock {
+            if writable && !readable && inner.readers == 0 {
+                return Err(());
+            }
+        } else if readable && !writable {
+            let nwriteopen = inner.nwriteopen;
+            while inner.writers == 0 && inner.nwriteopen == nwriteopen {
+                if (*myproc()).killed() {
+                    return Err(());
+                }
+                self.read_waitchannel.sleep(&mut inner);
+            }
+        } else if writable && !readable {
+            let nreadopen = inner.nreadopen;
+            while inner.readers == 0 && inner.nreadopen == nreadopen {
+                if (*myproc()).killed() {
+                    return Err(());
+                }
+                self.write_waitchannel.sleep(&mut inner);
+            }
+        }
+        Ok(())
+    }
+
+    unsafe fn close(&self, readable: bool, writable: bool) -> bool {
         let mut inner = self.inner.lock();
 
         if writable {
-            inner.writeopen = false;
+            inner.writers -= 1;
             self.read_waitchannel.wakeup();
-        } else {
-            inner.readopen = false;
+        }
+        if readable {
+            inner.readers -= 1;
             self.write_waitchannel.wakeup();
         }
 
-        // Return whether pipe would be freed or not
-        !inner.readopen && !inner.writeopen
+        if inner.readers == 0 && inner.writers == 0 {
+            // Unread data is discarded once nobody has the pipe open.
+            inner.nread = 0;
+            inner.nwrite = 0;
+            true
+        } else {
+            false
+        }
     }
 }
 
@@ -113,7 +172,8 @@ impl Deref for AllocatedPipe {
 }
 
 impl AllocatedPipe {
-    pub unsafe fn alloc() -> Result<(RcFile<'static>, RcFile<'static>), ()> {
+    /// Allocate a pipe with the given number of readers and writers.
+    pub unsafe fn new(readers: u32, writers: u32) -> Result<Self, ()> {
         let page = kernel().alloc().ok_or(())?;
         let ptr = page.into_usize() as *mut Pipe;
 
@@ -125,13 +185,20 @@ impl AllocatedPipe {
                     data: [0; PIPESIZE],
                     nwrite: 0,
                     nread: 0,
-                    readopen: true,
-                    writeopen: true,
+                    readers,
+                    writers,
+                    nreadopen: 0,
+                    nwriteopen: 0,
                 },
             ),
             read_waitchannel: WaitChannel::new(),
             write_waitchannel: WaitChannel::new(),
         };
+        Ok(Self { ptr })
+    }
+
+    pub unsafe fn alloc() -> Result<(RcFile<'static>, RcFile<'static>), ()> {
+        let ptr = Self::new(1, 1)?.ptr;
         let f0 = kernel()
             .ftable
             .alloc_file(FileType::Pipe { pipe: Self { ptr } }, true, false)
@@ -149,10 +216,20 @@ impl AllocatedPipe {
     // `&mut self` is used because `Drop` of `File` uses AllocatedPipe inside File.
     // https://github.com/kaist-cp/rv6/pull/211#discussion_r491671723
     pub unsafe fn close(&mut self, writable: bool) {
-        if (*self.ptr).close(writable) {
-            kernel().free(Page::from_usize(self.ptr as *mut Pipe as _));
+        if (*self.ptr).close(!writable, writable) {
+            self.free();
         }
     }
+
+    /// Close the ends of a FIFO opened by Pipe::open_fifo().
+    /// The pipe itself lives until the FIFO's inode is recycled.
+    pub unsafe fn close_fifo(&mut self, readable: bool, writable: bool) {
+        (*self.ptr).close(readable, writable);
+    }
+
+    pub unsafe fn free(self) {
+        kernel().free(Page::from_usize(self.ptr as *mut Pipe as _));
+    }
 }
 
 pub enum PipeError {
@@ -162,19 +239,18 @@ pub enum PipeError {
 }
 
 impl PipeInner {
-    unsafe fn try_write(&mut self, addr: UVAddr, n: usize) -> Result<usize, PipeError> {
+    unsafe fn try_write<A: VAddr>(&mut self, addr: A, n: usize) -> Result<usize, PipeError> {
         let mut ch = [0 as u8];
         let proc = myproc();
-        if !self.readopen || (*proc).killed() {
+        if self.readers == 0 || (*proc).killed() {
             return Err(PipeError::InvalidStatus);
         }
-        let data = &mut *(*proc).data.get();
         for i in 0..n {
             if self.nwrite == self.nread.wrapping_add(PIPESIZE as u32) {
                 //DOC: pipewrite-full
                 return Ok(i);
             }
-            if data.pagetable.copyin(&mut ch, addr + i).is_err() {
+            if VAddr::copyin(&mut ch, addr + i).is_err() {
                 return Err(PipeError::InvalidCopyin(i));
             }
             self.data[self.nwrite as usize % PIPESIZE] = ch[0];
@@ -183,12 +259,11 @@ impl PipeInner {
         Ok(n)
     }
 
-    unsafe fn try_read(&mut self, addr: UVAddr, n: usize) -> Result<usize, PipeError> {
+    unsafe fn try_read<A: VAddr>(&mut self, addr: A, n: usize) -> Result<usize, PipeError> {
         let proc = myproc();
-        let data = &mut *(*proc).data.get();
 
         //DOC: pipe-empty
-        if self.nread == self.nwrite && self.writeopen {
+        if self.nread == self.nwrite && self.writers > 0 {
             if (*proc).killed() {
                 return Err(PipeError::InvalidStatus);
             }
@@ -202,7 +277,7 @@ impl PipeInner {
             }
             let ch = [self.data[self.nread as usize % PIPESIZE]];
             self.nread = self.nread.wrapping_add(1);
-            if data.pagetable.copyout(addr + i, &ch).is_err() {
+            if VAddr::copyout(addr + i, &ch).is_err() {
                 return Ok(i);
             }
         }
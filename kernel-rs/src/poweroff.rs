@@ -1,6 +1,12 @@
 use crate::memlayout;
 use core::ptr;
 
+/// reboot(): power off the machine.
+pub const RB_POWER_OFF: i32 = 0x4321fedc;
+
+/// reboot(): reset the machine.
+pub const RB_AUTOBOOT: i32 = 0x01234567;
+
 /// Shutdowns this machine, discarding all unsaved data.
 ///
 /// This function uses SiFive Test Finalizer, which provides power management for QEMU virt device.
@@ -18,3 +24,16 @@ pub fn machine_poweroff(exitcode: u16) -> ! {
 
     unreachable!("Power off failed");
 }
+
+/// Resets this machine, discarding all unsaved data.
+///
+/// Like machine_poweroff, this uses SiFive Test Finalizer.
+pub fn machine_reboot() -> ! {
+    const RESET_CODE: u64 = 0x7777;
+    // SAFETY: see machine_poweroff.
+    unsafe {
+        ptr::write_volatile(memlayout::FINISHER as *mut u64, RESET_CODE);
+    }
+
+    unreachable!("Reboot failed");
+}
@@ -15,13 +15,15 @@ use crate::{
     memlayout::{kstack, TRAMPOLINE, TRAPFRAME},
     ok_or,
     page::Page,
-    param::{MAXPROCNAME, NOFILE, NPROC, ROOTDEV},
+    param::{MAXPROCNAME, NCPU, NOFILE, NOFILE_MAX, NPROC, ROOTDEV},
     println,
+    resource::{Rlimit, Rusage, RLIMIT_AS, RLIM_NLIMITS},
     riscv::{intr_get, intr_on, r_tp, PGSIZE, PTE_R, PTE_W, PTE_X},
     sleepablelock::SleepablelockGuard,
     some_or,
     spinlock::{pop_off, push_off, RawSpinlock, Spinlock, SpinlockGuard},
     string::safestrcpy,
+    timer::Timer,
     trap::usertrapret,
     vm::{KVAddr, PAddr, PageTable, UVAddr, VAddr},
 };
@@ -70,6 +72,9 @@ pub struct Cpu {
 
     /// Were interrupts enabled before push_off()?
     pub interrupt_enabled: bool,
+
+    /// Is this cpu running a device interrupt handler? See may_sleep().
+    pub in_devintr: bool,
 }
 
 /// Per-process data for the trap handling code in trampoline.S.
@@ -235,6 +240,7 @@ impl WaitChannel {
     // 1. Some static mut variables are still not Spinlock<T> but RawSpinlock
     // 2. Sleeplock doesn't have Spinlock<T>
     pub unsafe fn sleep_raw(&self, lk: *const RawSpinlock) {
+        debug_assert!(may_sleep(), "sleep in a device interrupt handler");
         let p: *mut Proc = myproc();
 
         // Must acquire p->lock in order to
@@ -249,6 +255,7 @@ impl WaitChannel {
         (*lk).release();
 
         // Go to sleep.
+        (*(*p).data.get()).rusage.nvcsw += 1;
         let mut guard = ProcGuard::from_raw(p);
         guard.deref_mut_info().waitchannel = self;
         guard.deref_mut_info().state = Procstate::SLEEPING;
@@ -270,6 +277,13 @@ impl WaitChannel {
     }
 }
 
+/// May the current cpu sleep? Not while it runs a device interrupt handler:
+/// the handler runs on behalf of no process, on the stack of whatever it interrupted.
+/// Must be called with interrupts disabled.
+pub unsafe fn may_sleep() -> bool {
+    !(*kernel().mycpu()).in_devintr
+}
+
 /// Proc::info's spinlock must be held when using these.
 struct ProcInfo {
     /// Process state.
@@ -290,8 +304,19 @@ struct ProcInfo {
 
     /// Process ID.
     pid: i32,
+
+    /// CPUs the process may run on, one bit per CPU id.
+    affinity: u64,
 }
 
+/// Affinity of a new process: every CPU.
+const ALL_CPUS: u64 = (1 << NCPU) - 1;
+
+/// Table of open files beyond the first NOFILE, which takes one page.
+type OverflowFiles = [Option<RcFile<'static>>; NOFILE_MAX - NOFILE];
+
+const_assert!(mem::size_of::<OverflowFiles>() <= PGSIZE);
+
 /// Proc::data are private to the process, so lock need not be held.
 pub struct ProcData {
     /// Virtual address of kernel stack.
@@ -310,10 +335,26 @@ pub struct ProcData {
     context: Context,
 
     /// Open files.
-    pub open_files: [Option<RcFile<'static>>; NOFILE],
+    /// File descriptors from NOFILE on are in open_files_overflow.
+    open_files: [Option<RcFile<'static>>; NOFILE],
+
+    /// Open files NOFILE..NOFILE_MAX, allocated when first needed.
+    open_files_overflow: *mut OverflowFiles,
 
     /// Current directory.
     pub cwd: Option<RcInode<'static>>,
+
+    /// Root directory, where absolute paths start and `..` cannot go above.
+    pub root: Option<RcInode<'static>>,
+
+    /// Resource usage of this process.
+    pub rusage: Rusage,
+
+    /// Resource usage of terminated children that have been waited for.
+    pub child_rusage: Rusage,
+
+    /// Resource limits, indexed by RLIMIT_*.
+    pub rlimits: [Rlimit; RLIM_NLIMITS],
 }
 
 /// Per-process state.
@@ -327,6 +368,9 @@ pub struct Proc {
 
     /// Process name (debugging).
     pub name: [u8; MAXPROCNAME],
+
+    /// Timer used by sleep(). Protected by kernel().ticks.
+    pub timer: UnsafeCell<Timer>,
 }
 
 /// Assumption: `ptr` is `myproc()`, and ptr->info's spinlock is held.
@@ -402,6 +446,7 @@ impl Cpu {
             context: Context::new(),
             noff: 0,
             interrupt_enabled: false,
+            in_devintr: false,
         }
     }
 }
@@ -449,17 +494,63 @@ impl ProcData {
             trapframe: ptr::null_mut(),
             context: Context::new(),
             open_files: [None; NOFILE],
+            open_files_overflow: ptr::null_mut(),
             cwd: None,
+            root: None,
+            rusage: Rusage::zero(),
+            child_rusage: Rusage::zero(),
+            rlimits: [Rlimit::infinity(); RLIM_NLIMITS],
+        }
+    }
+
+    /// Return the slot of file descriptor fd, or None if fd is out of range
+    /// or its overflow table is not allocated.
+    pub fn open_file(&mut self, fd: usize) -> Option<&mut Option<RcFile<'static>>> {
+        if fd < NOFILE {
+            Some(&mut self.open_files[fd])
+        } else if fd < NOFILE_MAX && !self.open_files_overflow.is_null() {
+            Some(unsafe { &mut (*self.open_files_overflow)[fd - NOFILE] })
+        } else {
+            None
+        }
+    }
+
+    /// Like open_file(), but allocate the overflow table if fd needs it.
+    pub unsafe fn open_file_alloc(&mut self, fd: usize) -> Option<&mut Option<RcFile<'static>>> {
+        if fd >= NOFILE && self.open_files_overflow.is_null() {
+            self.alloc_overflow().ok()?;
+        }
+        self.open_file(fd)
+    }
+
+    unsafe fn alloc_overflow(&mut self) -> Result<(), ()> {
+        let table = kernel().alloc().ok_or(())?.into_usize() as *mut OverflowFiles;
+        for file in (*table).iter_mut() {
+            ptr::write(file, None);
+        }
+        self.open_files_overflow = table;
+        Ok(())
+    }
+
+    /// Free the overflow table. Its files must be closed already.
+    unsafe fn free_overflow(&mut self) {
+        if !self.open_files_overflow.is_null() {
+            kernel().free(Page::from_usize(self.open_files_overflow as _));
+            self.open_files_overflow = ptr::null_mut();
         }
     }
 
     /// Close all open files.
     unsafe fn close_files(&mut self) {
-        for file in &mut self.open_files {
-            *file = None;
+        for fd in 0..NOFILE_MAX {
+            if let Some(file) = self.open_file(fd) {
+                *file = None;
+            }
         }
+        self.free_overflow();
         let _tx = kernel().fs().begin_transaction();
         self.cwd = None;
+        self.root = None;
     }
 }
 
@@ -476,11 +567,13 @@ impl Proc {
                     waitchannel: ptr::null(),
                     xstate: 0,
                     pid: 0,
+                    affinity: ALL_CPUS,
                 },
             ),
             data: UnsafeCell::new(ProcData::new()),
             killed: AtomicBool::new(false),
             name: [0; MAXPROCNAME],
+            timer: UnsafeCell::new(Timer::zero()),
         }
     }
 
@@ -489,6 +582,15 @@ impl Proc {
         ProcGuard { ptr: self }
     }
 
+    /// Wake up the process if it is sleeping on waitchannel.
+    /// Must be called without p->lock.
+    pub fn wakeup_on(&self, target: &WaitChannel) {
+        let mut guard = self.lock();
+        if guard.deref_info().waitchannel == target as _ {
+            guard.wakeup()
+        }
+    }
+
     pub unsafe fn pid(&self) -> i32 {
         self.info.get_mut_unchecked().pid
     }
@@ -614,6 +716,31 @@ impl ProcessSystem {
         -1
     }
 
+    /// Restrict the process with the given pid to the CPUs in mask.
+    /// Returns Err(()) if there is no such process.
+    pub fn set_affinity(&self, pid: i32, mask: u64) -> Result<(), ()> {
+        for p in &self.process_pool {
+            let mut guard = p.lock();
+            if guard.deref_info().pid == pid {
+                guard.deref_mut_info().affinity = mask;
+                return Ok(());
+            }
+        }
+        Err(())
+    }
+
+    /// Return the CPUs the process with the given pid may run on.
+    /// Returns Err(()) if there is no such process.
+    pub fn affinity(&self, pid: i32) -> Result<u64, ()> {
+        for p in &self.process_pool {
+            let guard = p.lock();
+            if guard.deref_info().pid == pid {
+                return Ok(guard.deref_info().affinity);
+            }
+        }
+        Err(())
+    }
+
     /// Wake up all processes in the pool sleeping on waitchannel.
     /// Must be called without any p->lock.
     pub fn wakeup_pool(&self, target: &WaitChannel) {
@@ -653,6 +780,7 @@ impl ProcessSystem {
             mem::size_of::<[u8; MAXPROCNAME]>() as i32,
         );
         data.cwd = Some(Path::root());
+        data.root = Some(Path::root());
         guard.deref_mut_info().state = Procstate::RUNNABLE;
     }
 
@@ -660,6 +788,7 @@ impl ProcessSystem {
     /// Sets up child kernel stack to return as if from fork() system call.
     pub unsafe fn fork(&self) -> i32 {
         let p = myproc();
+        let affinity = (*p).lock().deref_info().affinity;
 
         // Allocate process.
         let mut np = ok_or!(self.alloc(), return -1);
@@ -675,7 +804,12 @@ impl ProcessSystem {
             freeproc(np);
             return -1;
         }
+        if !pdata.open_files_overflow.is_null() && npdata.alloc_overflow().is_err() {
+            freeproc(np);
+            return -1;
+        }
         npdata.sz = pdata.sz;
+        npdata.rlimits = pdata.rlimits;
 
         // Copy saved user registers.
         *npdata.trapframe = *pdata.trapframe;
@@ -684,12 +818,13 @@ impl ProcessSystem {
         (*npdata.trapframe).a0 = 0;
 
         // Increment reference counts on open file descriptors.
-        for i in 0..NOFILE {
-            if let Some(file) = &pdata.open_files[i] {
-                npdata.open_files[i] = Some(file.clone())
+        for fd in 0..NOFILE_MAX {
+            if let Some(Some(file)) = pdata.open_file(fd) {
+                *npdata.open_file(fd).unwrap() = Some(file.clone())
             }
         }
         npdata.cwd = Some(pdata.cwd.clone().unwrap());
+        npdata.root = Some(pdata.root.clone().unwrap());
 
         safestrcpy(
             (*np).name.as_mut_ptr(),
@@ -707,6 +842,7 @@ impl ProcessSystem {
         self.wait_lock.release();
 
         let mut np = (*child).lock();
+        np.deref_mut_info().affinity = affinity;
         np.deref_mut_info().state = Procstate::RUNNABLE;
 
         pid
@@ -748,6 +884,9 @@ impl ProcessSystem {
                             self.wait_lock.release();
                             return -1;
                         }
+                        let child = &*np.data.get();
+                        data.child_rusage.add(&child.rusage);
+                        data.child_rusage.add(&child.child_rusage);
                         freeproc(np);
                         self.wait_lock.release();
                         return pid;
@@ -882,12 +1021,16 @@ unsafe fn freeproc(mut p: ProcGuard) {
     }
     data.pagetable = PageTable::zero();
     data.sz = 0;
+    data.free_overflow();
+    data.rusage = Rusage::zero();
+    data.child_rusage = Rusage::zero();
     p.deref_mut_info().pid = 0;
     p.deref_mut_info().parent = ptr::null_mut();
     (*p).name[0] = 0;
     p.deref_mut_info().waitchannel = ptr::null();
     p.killed = AtomicBool::new(false);
     p.deref_mut_info().xstate = 0;
+    p.deref_mut_info().affinity = ALL_CPUS;
     p.deref_mut_info().state = Procstate::UNUSED;
 }
 
@@ -957,6 +1100,9 @@ pub unsafe fn resizeproc(n: i32) -> i32 {
     let sz = match n.cmp(&0) {
         cmp::Ordering::Equal => sz,
         cmp::Ordering::Greater => {
+            if sz as u64 + n as u64 > data.rlimits[RLIMIT_AS as usize].cur {
+                return -1;
+            }
             let sz = data.pagetable.uvmalloc(sz, sz.wrapping_add(n as usize));
             ok_or!(sz, return -1)
         }
@@ -976,13 +1122,16 @@ pub unsafe fn resizeproc(n: i32) -> i32 {
 pub unsafe fn scheduler() -> ! {
     let mut c = kernel().mycpu();
     (*c).proc = ptr::null_mut();
+    let cpu = 1 << cpuid();
     loop {
         // Avoid deadlock by ensuring that devices can interrupt.
         intr_on();
 
         for p in &kernel().procs.process_pool {
             let mut guard = p.lock();
-            if guard.deref_info().state == Procstate::RUNNABLE {
+            if guard.deref_info().state == Procstate::RUNNABLE
+                && guard.deref_info().affinity & cpu != 0
+            {
                 // Switch to chosen process.  It is the process's job
                 // to release its lock and then reacquire it
                 // before jumping back to us.
@@ -998,8 +1147,21 @@ pub unsafe fn scheduler() -> ! {
     }
 }
 
-/// Give up the CPU for one scheduling round.
+/// Give up the CPU for one scheduling round, because the timer preempted us.
+/// Counts as an involuntary context switch.
 pub unsafe fn proc_yield() {
+    (*(*myproc()).data.get()).rusage.nivcsw += 1;
+    yield_cpu();
+}
+
+/// Give up the CPU for one scheduling round at the process's own request.
+/// Counts as a voluntary context switch.
+pub unsafe fn proc_yield_voluntary() {
+    (*(*myproc()).data.get()).rusage.nvcsw += 1;
+    yield_cpu();
+}
+
+unsafe fn yield_cpu() {
     let p = myproc();
     let mut guard = (*p).lock();
     guard.deref_mut_info().state = Procstate::RUNNABLE;
@@ -0,0 +1,90 @@
+//! Per-process resource accounting.
+
+/// getrusage(): usage of the calling process.
+pub const RUSAGE_SELF: i32 = 0;
+
+/// getrusage(): usage of the terminated children the caller has waited for.
+pub const RUSAGE_CHILDREN: i32 = -1;
+
+// It needs repr(C) because it's copied out to user space,
+// and should follow the layout of struct rusage in kernel/resource.h.
+#[repr(C)]
+#[derive(Default, Copy, Clone)]
+pub struct Rusage {
+    /// Clock ticks spent in user mode.
+    pub utime: u64,
+
+    /// Clock ticks spent in the kernel.
+    pub stime: u64,
+
+    /// Blocks read from disk.
+    pub inblock: u64,
+
+    /// Blocks written to disk.
+    pub oublock: u64,
+
+    /// Voluntary context switches (sleep).
+    pub nvcsw: u64,
+
+    /// Involuntary context switches (timer interrupt).
+    pub nivcsw: u64,
+}
+
+impl Rusage {
+    pub const fn zero() -> Self {
+        Self {
+            utime: 0,
+            stime: 0,
+            inblock: 0,
+            oublock: 0,
+            nvcsw: 0,
+            nivcsw: 0,
+        }
+    }
+
+    /// Add the counters of other to self.
+    pub fn add(&mut self, other: &Self) {
+        self.utime += other.utime;
+        self.stime += other.stime;
+        self.inblock += other.inblock;
+        self.oublock += other.oublock;
+        self.nvcsw += other.nvcsw;
+        self.nivcsw += other.nivcsw;
+    }
+}
+
+/// Maximum number of open file descriptors.
+pub const RLIMIT_NOFILE: i32 = 0;
+
+/// Maximum size of the process's memory, in bytes.
+pub const RLIMIT_AS: i32 = 1;
+
+/// Maximum size of a file the process may write, in bytes.
+pub const RLIMIT_FSIZE: i32 = 2;
+
+/// Number of resource limits.
+pub const RLIM_NLIMITS: usize = 3;
+
+/// No limit.
+pub const RLIM_INFINITY: u64 = u64::MAX;
+
+// It needs repr(C) because it's copied to and from user space,
+// and should follow the layout of struct rlimit in kernel/resource.h.
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct Rlimit {
+    /// Soft limit, which is enforced.
+    pub cur: u64,
+
+    /// Hard limit, the ceiling for cur.
+    pub max: u64,
+}
+
+impl Rlimit {
+    pub const fn infinity() -> Self {
+        Self {
+            cur: RLIM_INFINITY,
+            max: RLIM_INFINITY,
+        }
+    }
+}
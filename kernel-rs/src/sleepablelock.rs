@@ -36,6 +36,7 @@ impl<T> Sleepablelock<T> {
         self.data.into_inner()
     }
 
+    /// Acquire the lock by spinning. This never sleeps, so it may be used in interrupt context.
     pub fn lock(&self) -> SleepablelockGuard<'_, T> {
         self.lock.acquire();
 
@@ -10,6 +10,12 @@ pub const T_FILE: i16 = 2;
 /// Device
 pub const T_DEVICE: i16 = 3;
 
+/// Named pipe
+pub const T_FIFO: i16 = 4;
+
+// It needs repr(C) because it's copied out to user space,
+// and should follow the layout of struct stat in kernel/stat.h.
+#[repr(C)]
 #[derive(Default, Copy, Clone)]
 pub struct Stat {
     /// File system's disk device
@@ -24,6 +30,36 @@ pub struct Stat {
     /// Number of links to file
     pub nlink: i16,
 
+    /// Device a device file refers to, made by mkdev(). 0 for other files.
+    pub rdev: u32,
+
     /// Size of file in bytes
     pub size: usize,
 }
+
+/// Encode a device's major and minor numbers, as MKDEV in kernel/stat.h.
+pub const fn mkdev(major: u16, minor: u16) -> u32 {
+    (major as u32) << 16 | minor as u32
+}
+
+/// File system-wide usage, as reported by statfs().
+// It needs repr(C) because it's copied out to user space,
+// and should follow the layout of struct statfs in kernel/stat.h.
+#[repr(C)]
+#[derive(Default, Copy, Clone)]
+pub struct Statfs {
+    /// Block size in bytes
+    pub bsize: u32,
+
+    /// Number of data blocks
+    pub blocks: u32,
+
+    /// Number of free data blocks
+    pub bfree: u32,
+
+    /// Number of inodes
+    pub files: u32,
+
+    /// Number of free inodes
+    pub ffree: u32,
+}
@@ -31,6 +31,7 @@ pub unsafe fn fetchaddr(addr: UVAddr, ip: *mut usize) -> i32 {
 
 /// Fetch the nul-terminated string at addr from the current process.
 /// Returns reference to the string in the buffer.
+/// Returns Err if there is no nul within buf.len() bytes.
 pub unsafe fn fetchstr(addr: UVAddr, buf: &mut [u8]) -> Result<&CStr, ()> {
     let p: *mut Proc = myproc();
     (*(*p).data.get()).pagetable.copyinstr(buf, addr)?;
@@ -65,8 +66,9 @@ pub unsafe fn argaddr(n: usize) -> Result<usize, ()> {
 }
 
 /// Fetch the nth word-sized system call argument as a null-terminated string.
-/// Copies into buf, at most max.
-/// Returns string length if OK (including nul), -1 if error.
+/// Copies into buf, at most buf.len() bytes including the nul.
+/// Returns Err if the string does not fit in buf, so a truncated
+/// path is never passed on to namei.
 pub unsafe fn argstr(n: usize, buf: &mut [u8]) -> Result<&CStr, ()> {
     let addr = argaddr(n)?;
     fetchstr(UVAddr::new(addr), buf)
@@ -101,6 +103,24 @@ impl Kernel {
             20 => self.sys_mkdir(),
             21 => self.sys_close(),
             22 => self.sys_poweroff(),
+            23 => self.sys_getrusage(),
+            24 => self.sys_getrlimit(),
+            25 => self.sys_setrlimit(),
+            26 => self.sys_statfs(),
+            27 => self.sys_execve(),
+            28 => self.sys_rmdir(),
+            29 => self.sys_mkfifo(),
+            30 => self.sys_chroot(),
+            31 => self.sys_reboot(),
+            32 => self.sys_mprotect(),
+            33 => self.sys_close_range(),
+            34 => self.sys_prlimit(),
+            35 => self.sys_sched_setaffinity(),
+            36 => self.sys_sched_getaffinity(),
+            37 => self.sys_getcpu(),
+            38 => self.sys_splice(),
+            39 => self.sys_fsck(),
+            40 => self.sys_dmesg(),
             _ => {
                 println!(
                     "{} {}: unknown sys call {}",
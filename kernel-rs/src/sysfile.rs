@@ -10,29 +10,32 @@ use crate::{
     fs::{Dirent, FileName, FsTransaction, InodeGuard, Path, RcInode, DIRENT_SIZE},
     kernel::{kernel, Kernel},
     ok_or,
-    page::Page,
-    param::{MAXARG, MAXPATH, NDEV, NOFILE},
+    param::{MAXPATH, NDEV, NOFILE_MAX, ROOTDEV},
     pipe::AllocatedPipe,
     proc::{myproc, Proc},
-    riscv::PGSIZE,
+    resource::RLIMIT_NOFILE,
     some_or,
-    stat::{T_DEVICE, T_DIR, T_FILE},
-    syscall::{argaddr, argint, argstr, fetchaddr, fetchstr},
+    stat::{Statfs, T_DEVICE, T_DIR, T_FIFO, T_FILE},
+    syscall::{argaddr, argint, argstr},
     vm::{KVAddr, UVAddr, VAddr},
 };
 
-use core::{cell::UnsafeCell, mem, ptr, slice};
+use core::{cell::UnsafeCell, cmp, convert::TryFrom, mem, slice};
+
+/// Number of bytes splice moves through its kernel buffer at a time.
+const SPLICE_CHUNK: usize = 512;
 
 impl RcFile<'static> {
     /// Allocate a file descriptor for the given file.
     /// Takes over file reference from caller on success.
     unsafe fn fdalloc(self) -> Result<i32, Self> {
         let p: *mut Proc = myproc();
-        let mut data = &mut *(*p).data.get();
-        for fd in 0..NOFILE {
-            // user pointer to struct stat
-            if data.open_files[fd].is_none() {
-                data.open_files[fd] = Some(self);
+        let data = &mut *(*p).data.get();
+        let limit = cmp::min(data.rlimits[RLIMIT_NOFILE as usize].cur, NOFILE_MAX as u64) as usize;
+        for fd in 0..limit {
+            let file = some_or!(data.open_file_alloc(fd), break);
+            if file.is_none() {
+                *file = Some(self);
                 return Ok(fd as i32);
             }
         }
@@ -44,18 +47,52 @@ impl RcFile<'static> {
 /// and return both the descriptor and the corresponding struct file.
 unsafe fn argfd(n: usize) -> Result<(i32, &'static RcFile<'static>), ()> {
     let fd = argint(n)?;
-    if fd < 0 || fd >= NOFILE as i32 {
+    if fd < 0 {
         return Err(());
     }
 
     let f = some_or!(
-        &(*(*myproc()).data.get()).open_files[fd as usize],
+        some_or!(
+            (*(*myproc()).data.get()).open_file(fd as usize),
+            return Err(())
+        ),
         return Err(())
     );
 
     Ok((fd, f))
 }
 
+/// Open the FIFO ip, and return the file descriptor.
+unsafe fn open_fifo(ip: RcInode<'static>, omode: FcntlFlags) -> Result<usize, ()> {
+    let readable = !omode.intersects(FcntlFlags::O_WRONLY);
+    let writable = omode.intersects(FcntlFlags::O_WRONLY | FcntlFlags::O_RDWR);
+
+    // ip may be the last reference to an unlinked FIFO,
+    // so it must be dropped in a transaction on failure.
+    let (f, pipe) = {
+        let tx = kernel().fs().begin_transaction();
+        let pipe = ip.lock(&tx).fifo_pipe();
+        let pipe = match pipe {
+            Ok(pipe) => pipe,
+            Err(()) => {
+                drop(ip);
+                return Err(());
+            }
+        };
+        let f = kernel()
+            .ftable
+            .alloc_file(FileType::Fifo { ip, pipe }, readable, writable)
+            .ok_or(())?;
+        drop(tx);
+        (f, pipe)
+    };
+
+    // If this fails, dropping f closes the ends that were opened.
+    pipe.open_fifo(readable, writable, omode.contains(FcntlFlags::O_NONBLOCK))?;
+    let fd = f.fdalloc().map_err(|_| ())?;
+    Ok(fd as usize)
+}
+
 unsafe fn create<F, T>(
     path: &Path,
     typ: i16,
@@ -72,7 +109,11 @@ where
     if let Ok((ptr2, _)) = dp.dirlookup(&name) {
         drop(dp);
         let mut ip = ptr2.lock(tx);
-        if typ == T_FILE && (ip.deref_inner().typ == T_FILE || ip.deref_inner().typ == T_DEVICE) {
+        if typ == T_FILE
+            && (ip.deref_inner().typ == T_FILE
+                || ip.deref_inner().typ == T_DEVICE
+                || ip.deref_inner().typ == T_FIFO)
+        {
             let ret = f(&mut ip);
             mem::drop(ip);
             return Ok((ptr2, ret));
@@ -87,22 +128,54 @@ where
     ip.update();
 
     // Create . and .. entries.
+    // No ip->nlink++ for ".": avoid cyclic ref count.
+    let dots = if typ == T_DIR {
+        ip.dirlink(FileName::from_bytes(b"."), ip.inum)
+            .and_then(|_| ip.dirlink(FileName::from_bytes(b".."), dp.inum))
+    } else {
+        Ok(())
+    };
+    if dots.and_then(|_| dp.dirlink(&name, ip.inum)).is_err() {
+        // The parent directory is corrupt or out of space.
+        // Free the new inode instead of linking it.
+        ip.deref_inner_mut().nlink = 0;
+        ip.update();
+        return Err(());
+    }
     if typ == T_DIR {
         // for ".."
         dp.deref_inner_mut().nlink += 1;
         dp.update();
-
-        // No ip->nlink++ for ".": avoid cyclic ref count.
-        ip.dirlink(FileName::from_bytes(b"."), ip.inum)
-            .and_then(|_| ip.dirlink(FileName::from_bytes(b".."), dp.inum))
-            .expect("create dots");
     }
-    dp.dirlink(&name, ip.inum).expect("create: dirlink");
     let ret = f(&mut ip);
     mem::drop(ip);
     Ok((ptr2, ret))
 }
 
+/// Fetch the 64-bit file offset at the user address addr, if addr is not null.
+unsafe fn fetch_offset(addr: usize) -> Result<Option<u32>, ()> {
+    if addr == 0 {
+        return Ok(None);
+    }
+    let mut bytes = [0u8; mem::size_of::<u64>()];
+    (*(*myproc()).data.get())
+        .pagetable
+        .copyin(&mut bytes, UVAddr::new(addr))?;
+    Ok(Some(
+        u32::try_from(u64::from_ne_bytes(bytes)).map_err(|_| ())?,
+    ))
+}
+
+/// Store the file offset off at the user address addr, if addr is not null.
+unsafe fn store_offset(addr: usize, off: Option<u32>) -> Result<(), ()> {
+    match off {
+        Some(off) => (*(*myproc()).data.get())
+            .pagetable
+            .copyout(UVAddr::new(addr), &(off as u64).to_ne_bytes()),
+        None => Ok(()),
+    }
+}
+
 impl Kernel {
     pub unsafe fn sys_dup(&self) -> usize {
         let (_, f) = ok_or!(argfd(0), return usize::MAX);
@@ -128,7 +201,26 @@ impl Kernel {
 
     pub unsafe fn sys_close(&self) -> usize {
         let (fd, _) = ok_or!(argfd(0), return usize::MAX);
-        (*(*myproc()).data.get()).open_files[fd as usize] = None;
+        *(*(*myproc()).data.get()).open_file(fd as usize).unwrap() = None;
+        0
+    }
+
+    /// Close every open file descriptor from first to last, inclusive.
+    /// last may exceed the largest descriptor, e.g. ~0U to close all from first on.
+    /// flags must be 0: rv6 has no close-on-exec for CLOSE_RANGE_CLOEXEC to set.
+    pub unsafe fn sys_close_range(&self) -> usize {
+        let first = ok_or!(argint(0), return usize::MAX) as u32 as usize;
+        let last = ok_or!(argint(1), return usize::MAX) as u32 as usize;
+        let flags = ok_or!(argint(2), return usize::MAX);
+        if first > last || flags != 0 {
+            return usize::MAX;
+        }
+        let data = &mut *(*myproc()).data.get();
+        for fd in first..=cmp::min(last, NOFILE_MAX - 1) {
+            if let Some(f) = data.open_file(fd) {
+                *f = None;
+            }
+        }
         0
     }
 
@@ -148,29 +240,43 @@ impl Kernel {
         let new = ok_or!(argstr(1, &mut new), return usize::MAX);
         let tx = self.fs().begin_transaction();
         let ptr = ok_or!(Path::new(old).namei(&tx), return usize::MAX);
-        let mut ip = ptr.lock(&tx);
-        if ip.deref_inner().typ == T_DIR {
+        // Checked first, so that the inode is never the directory locked below.
+        // The type of an inode cannot change while `ptr` refers to it.
+        if !ptr.lock(&tx).may_link() {
             return usize::MAX;
         }
-        ip.deref_inner_mut().nlink += 1;
-        ip.update();
-        drop(ip);
+        let (ptr2, name) = ok_or!(Path::new(new).nameiparent(&tx), return usize::MAX);
 
-        if let Ok((ptr2, name)) = Path::new(new).nameiparent(&tx) {
-            let mut dp = ptr2.lock(&tx);
-            if dp.dev != ptr.dev || dp.dirlink(name, ptr.inum).is_err() {
-            } else {
-                return 0;
-            }
+        // Lock the directory before the inode, like create() and unlink().
+        // Everything that can fail happens before nlink is bumped, and both
+        // changes are in the same transaction, so a failed link changes nothing.
+        let mut dp = ptr2.lock(&tx);
+        if dp.dev != ptr.dev || dp.dirlookup(name).is_ok() {
+            return usize::MAX;
         }
-
         let mut ip = ptr.lock(&tx);
-        ip.deref_inner_mut().nlink -= 1;
+        if dp.dirlink(name, ptr.inum).is_err() {
+            return usize::MAX;
+        }
+        ip.deref_inner_mut().nlink += 1;
         ip.update();
-        usize::MAX
+        0
     }
 
+    /// Remove the directory entry of a non-directory.
     pub unsafe fn sys_unlink(&self) -> usize {
+        self.unlink(false)
+    }
+
+    /// Remove an empty directory.
+    pub unsafe fn sys_rmdir(&self) -> usize {
+        self.unlink(true)
+    }
+
+    /// Remove the directory entry named by the path argument.
+    /// The entry must refer to a directory if dir is true,
+    /// and to a non-directory otherwise.
+    unsafe fn unlink(&self, dir: bool) -> usize {
         let mut de: Dirent = Default::default();
         let mut path: [u8; MAXPATH] = [0; MAXPATH];
         let path = ok_or!(argstr(0, &mut path), return usize::MAX);
@@ -179,19 +285,21 @@ impl Kernel {
         let mut dp = ptr.lock(&tx);
 
         // Cannot unlink "." or "..".
-        if !(name.as_bytes() == b"." || name.as_bytes() == b"..") {
+        if !name.is_dot_or_dotdot() {
             // TODO: use other Result related functions
             if let Ok((ptr2, off)) = dp.dirlookup(&name) {
                 let mut ip = ptr2.lock(&tx);
                 assert!(ip.deref_inner().nlink >= 1, "unlink: nlink < 1");
 
-                if ip.deref_inner().typ != T_DIR || ip.isdirempty() {
+                if (ip.deref_inner().typ == T_DIR) == dir && ip.may_unlink() {
                     let bytes_write = dp.write(
                         KVAddr::new(&mut de as *mut Dirent as usize),
                         off,
                         DIRENT_SIZE as u32,
                     );
-                    assert_eq!(bytes_write, Ok(DIRENT_SIZE), "unlink: writei");
+                    if bytes_write != Ok(DIRENT_SIZE) {
+                        return usize::MAX;
+                    }
                     if ip.deref_inner().typ == T_DIR {
                         dp.deref_inner_mut().nlink -= 1;
                         dp.update();
@@ -240,6 +348,11 @@ impl Kernel {
         if typ == T_DEVICE && (major as usize >= NDEV) {
             return usize::MAX;
         }
+        if typ == T_FIFO {
+            // Opening a FIFO may wait for the other end, so it must not hold the transaction.
+            drop(tx);
+            return ok_or!(open_fifo(ip, omode), usize::MAX);
+        }
 
         let filetype = if typ == T_DEVICE {
             let major = major;
@@ -293,6 +406,49 @@ impl Kernel {
         0
     }
 
+    /// Create a FIFO (named pipe) at path.
+    pub unsafe fn sys_mkfifo(&self) -> usize {
+        let mut path: [u8; MAXPATH] = [0; MAXPATH];
+        let path = ok_or!(argstr(0, &mut path), return usize::MAX);
+        let tx = self.fs().begin_transaction();
+        let _ip = ok_or!(
+            create(Path::new(path), T_FIFO, 0, 0, &tx, |_| ()),
+            return usize::MAX
+        );
+        0
+    }
+
+    /// Report usage of the file system containing path.
+    pub unsafe fn sys_statfs(&self) -> usize {
+        let mut path: [u8; MAXPATH] = [0; MAXPATH];
+        let path = ok_or!(argstr(0, &mut path), return usize::MAX);
+        let addr = ok_or!(argaddr(1), return usize::MAX);
+        let tx = self.fs().begin_transaction();
+        let ptr = ok_or!(Path::new(path).namei(&tx), return usize::MAX);
+        let dev = ptr.dev;
+        drop(ptr);
+        drop(tx);
+        let mut st = self.fs().statfs(dev);
+        ok_or!(
+            (*(*myproc()).data.get()).pagetable.copyout(
+                UVAddr::new(addr),
+                slice::from_raw_parts_mut(
+                    &mut st as *mut Statfs as *mut u8,
+                    mem::size_of::<Statfs>(),
+                ),
+            ),
+            return usize::MAX
+        );
+        0
+    }
+
+    /// Check the consistency of the file system, printing each problem to the console.
+    /// Returns the number of problems found.
+    /// rv6 has no users, so any process may call it.
+    pub unsafe fn sys_fsck(&self) -> usize {
+        ok_or!(self.itable.fsck(ROOTDEV), usize::MAX)
+    }
+
     pub unsafe fn sys_chdir(&self) -> usize {
         let mut path: [u8; MAXPATH] = [0; MAXPATH];
         let p: *mut Proc = myproc();
@@ -309,63 +465,60 @@ impl Kernel {
         0
     }
 
-    pub unsafe fn sys_exec(&self) -> usize {
+    /// Make path the root directory of the process.
+    /// The current directory is left as it is.
+    pub unsafe fn sys_chroot(&self) -> usize {
         let mut path: [u8; MAXPATH] = [0; MAXPATH];
-        let mut argv: [*mut u8; MAXARG] = [ptr::null_mut(); MAXARG];
+        let data = &mut *(*myproc()).data.get();
         let path = ok_or!(argstr(0, &mut path), return usize::MAX);
-        let uargv = ok_or!(argaddr(1), return usize::MAX);
-
-        let mut success = false;
-        for (i, arg) in argv.iter_mut().enumerate() {
-            let mut uarg = 0;
-            if fetchaddr(
-                UVAddr::new(uargv + mem::size_of::<usize>() * i),
-                &mut uarg as *mut usize,
-            ) < 0
-            {
-                break;
-            }
-
-            if uarg == 0 {
-                *arg = ptr::null_mut();
-                success = true;
-                break;
-            }
-
-            *arg = some_or!(self.alloc(), break).into_usize() as *mut _;
-
-            if fetchstr(UVAddr::new(uarg), slice::from_raw_parts_mut(*arg, PGSIZE)).is_err() {
-                break;
-            }
+        let tx = self.fs().begin_transaction();
+        let ptr = ok_or!(Path::new(path).namei(&tx), return usize::MAX);
+        let ip = ptr.lock(&tx);
+        if ip.deref_inner().typ != T_DIR {
+            return usize::MAX;
         }
+        mem::drop(ip);
+        data.root = Some(ptr);
+        0
+    }
 
-        let ret = if success {
-            ok_or!(self.exec(Path::new(path), &argv), usize::MAX)
-        } else {
-            usize::MAX
-        };
+    pub unsafe fn sys_exec(&self) -> usize {
+        let uargv = ok_or!(argaddr(1), return usize::MAX);
+        self.execve(uargv, 0)
+    }
 
-        for arg in &mut argv[..] {
-            if arg.is_null() {
-                break;
-            }
+    /// Like exec, but also passes the null-terminated environment array envp,
+    /// which may be null.
+    pub unsafe fn sys_execve(&self) -> usize {
+        let uargv = ok_or!(argaddr(1), return usize::MAX);
+        let uenvp = ok_or!(argaddr(2), return usize::MAX);
+        self.execve(uargv, uenvp)
+    }
 
-            self.free(Page::from_usize(*arg as _));
-        }
+    unsafe fn execve(&self, uargv: usize, uenvp: usize) -> usize {
+        let mut path: [u8; MAXPATH] = [0; MAXPATH];
+        let path = ok_or!(argstr(0, &mut path), return usize::MAX);
 
+        // The one kernel page the argument strings are copied through.
+        let mut buf = some_or!(self.alloc(), return usize::MAX);
+        let ret = ok_or!(
+            self.exec(Path::new(path), uargv, uenvp, &mut buf[..]),
+            usize::MAX
+        );
+        self.free(buf);
         ret
     }
 
     pub unsafe fn sys_pipe(&self) -> usize {
         let p: *mut Proc = myproc();
-        let mut data = &mut *(*p).data.get();
+        let data = &mut *(*p).data.get();
         // user pointer to array of two integers
         let fdarray = ok_or!(argaddr(0), return usize::MAX);
         let (pipereader, pipewriter) = ok_or!(AllocatedPipe::alloc(), return usize::MAX);
 
         let mut fd0 = ok_or!(pipereader.fdalloc(), return usize::MAX);
         let mut fd1 = ok_or!(pipewriter.fdalloc(), {
-            data.open_files[fd0 as usize] = None;
+            *data.open_file(fd0 as usize).unwrap() = None;
             return usize::MAX;
         });
 
@@ -387,10 +540,74 @@ impl Kernel {
                 )
                 .is_err()
         {
-            data.open_files[fd0 as usize] = None;
-            data.open_files[fd1 as usize] = None;
+            *data.open_file(fd0 as usize).unwrap() = None;
+            *data.open_file(fd1 as usize).unwrap() = None;
             return usize::MAX;
         }
         0
     }
+
+    /// Move up to len bytes from fd_in to fd_out within the kernel,
+    /// without copying them through user space. At least one must be a pipe.
+    /// If off_in (off_out) is not null, the file fd_in (fd_out) is read (written)
+    /// at the offset it points to, which is advanced instead of the file offset.
+    /// flags must be 0. Returns the number of bytes moved, which is less than len
+    /// at the end of a file, if a pipe had fewer bytes, at RLIMIT_FSIZE, or on an error
+    /// after some bytes were moved. Bytes taken from fd_in are lost if writing them
+    /// to fd_out fails, as happens when a pipe has no reader left.
+    pub unsafe fn sys_splice(&self) -> usize {
+        let (_, fin) = ok_or!(argfd(0), return usize::MAX);
+        let off_in = ok_or!(argaddr(1), return usize::MAX);
+        let (_, fout) = ok_or!(argfd(2), return usize::MAX);
+        let off_out = ok_or!(argaddr(3), return usize::MAX);
+        let len = ok_or!(argint(4), return usize::MAX);
+        let flags = ok_or!(argint(5), return usize::MAX);
+        if len < 0 || flags != 0 || !(fin.is_pipe() || fout.is_pipe()) {
+            return usize::MAX;
+        }
+
+        // A pipe has no offset.
+        if (off_in != 0 && fin.is_pipe()) || (off_out != 0 && fout.is_pipe()) {
+            return usize::MAX;
+        }
+        let mut pos_in = ok_or!(fetch_offset(off_in), return usize::MAX);
+        let mut pos_out = ok_or!(fetch_offset(off_out), return usize::MAX);
+
+        let mut buf = [0u8; SPLICE_CHUNK];
+        let mut moved = 0;
+        let mut failed = false;
+        while moved < len as usize {
+            // Don't take bytes from fd_in that fd_out could not accept.
+            let n = fout.write_fit(cmp::min(len as usize - moved, buf.len()), pos_out);
+            if n == 0 {
+                failed = true;
+                break;
+            }
+            let r = ok_or!(fin.read_kernel(&mut buf[..n], pos_in.as_mut()), {
+                failed = true;
+                break;
+            });
+            if r == 0 {
+                break;
+            }
+            let w = ok_or!(fout.write_kernel(&buf[..r], pos_out.as_mut()), {
+                failed = true;
+                break;
+            });
+            moved += w;
+
+            // Stop at a short write, and don't wait for a pipe to be refilled.
+            if w < r || r < n {
+                break;
+            }
+        }
+
+        if failed && moved == 0 {
+            return usize::MAX;
+        }
+        if store_offset(off_in, pos_in).is_err() || store_offset(off_out, pos_out).is_err() {
+            return usize::MAX;
+        }
+        moved
+    }
 }
@@ -1,7 +1,12 @@
+use core::{cmp, mem, slice};
+
 use crate::{
     kernel::Kernel,
-    ok_or, poweroff,
-    proc::{myproc, resizeproc},
+    ok_or,
+    param::ROOTDEV,
+    poweroff::{self, RB_AUTOBOOT, RB_POWER_OFF},
+    proc::{cpuid, myproc, proc_yield_voluntary, resizeproc},
+    resource::{Rlimit, Rusage, RLIM_NLIMITS, RUSAGE_CHILDREN, RUSAGE_SELF},
     syscall::{argaddr, argint},
     vm::{UVAddr, VAddr},
 };
@@ -34,15 +39,32 @@ impl Kernel {
         addr as usize
     }
 
+    /// Change the user permissions of the pages in [addr, addr + len) to prot.
+    /// See PageTable::mprotect.
+    pub unsafe fn sys_mprotect(&self) -> usize {
+        let addr = ok_or!(argaddr(0), return usize::MAX);
+        let len = ok_or!(argaddr(1), return usize::MAX);
+        let prot = ok_or!(argint(2), return usize::MAX);
+        let data = &mut *(*myproc()).data.get();
+        ok_or!(
+            data.pagetable
+                .mprotect(UVAddr::new(addr), len, prot, data.sz),
+            return usize::MAX
+        );
+        0
+    }
+
     pub unsafe fn sys_sleep(&self) -> usize {
         let n = ok_or!(argint(0), return usize::MAX);
         let mut ticks = self.ticks.lock();
-        let ticks0 = *ticks;
-        while ticks.wrapping_sub(ticks0) < n as u32 {
+        let ticks0 = ticks.ticks();
+        let timer = &mut *(*myproc()).timer.get();
+        while ticks.ticks().wrapping_sub(ticks0) < n as u32 {
             if (*myproc()).killed() {
                 return usize::MAX;
             }
-            ticks.sleep();
+            let left = (n as u32).wrapping_sub(ticks.ticks().wrapping_sub(ticks0));
+            ticks.sleep_timer(timer, left);
         }
         0
     }
@@ -52,14 +74,198 @@ impl Kernel {
         self.procs.kill(pid) as usize
     }
 
+    /// Restrict the process with the given pid, or the caller if pid is 0,
+    /// to the CPUs in mask. The mask must include a CPU that is running.
+    pub unsafe fn sys_sched_setaffinity(&self) -> usize {
+        let mut pid = ok_or!(argint(0), return usize::MAX);
+        let mask = ok_or!(argaddr(1), return usize::MAX) as u64;
+        if mask & self.online_cpus() == 0 {
+            return usize::MAX;
+        }
+        if pid == 0 {
+            pid = (*myproc()).pid();
+        }
+        ok_or!(self.procs.set_affinity(pid, mask), return usize::MAX);
+
+        // Move off this CPU right away if it is no longer allowed.
+        // This is the caller's own doing, so it is a voluntary switch.
+        if pid == (*myproc()).pid() && mask & (1 << cpuid()) == 0 {
+            proc_yield_voluntary();
+        }
+        0
+    }
+
+    /// Return the CPUs the process with the given pid, or the caller if pid is 0,
+    /// may run on.
+    pub unsafe fn sys_sched_getaffinity(&self) -> usize {
+        let mut pid = ok_or!(argint(0), return usize::MAX);
+        if pid == 0 {
+            pid = (*myproc()).pid();
+        }
+        ok_or!(self.procs.affinity(pid), return usize::MAX) as usize
+    }
+
+    /// Return the id of the CPU the caller is running on.
+    /// It may have moved to another CPU by the time it looks at the result.
+    pub unsafe fn sys_getcpu(&self) -> usize {
+        cpuid()
+    }
+
     /// return how many clock tick interrupts have occurred
     /// since start.
     pub unsafe fn sys_uptime(&self) -> usize {
-        *self.ticks.lock() as usize
+        self.ticks.lock().ticks() as usize
+    }
+
+    /// Copy the most recent kernel messages, at most size bytes, to buf, oldest first.
+    /// Returns the number of bytes copied.
+    pub unsafe fn sys_dmesg(&self) -> usize {
+        let addr = ok_or!(argaddr(0), return usize::MAX);
+        let size = ok_or!(argint(1), return usize::MAX);
+        if size < 0 {
+            return usize::MAX;
+        }
+
+        let printer = self.printer.lock();
+        let (older, newer) = printer.msgs();
+        // Leave out the oldest bytes that don't fit.
+        let skip = (older.len() + newer.len()).saturating_sub(size as usize);
+        let newer = &newer[skip.saturating_sub(older.len())..];
+        let older = &older[cmp::min(skip, older.len())..];
+        let data = &mut *(*myproc()).data.get();
+        if data.pagetable.copyout(UVAddr::new(addr), older).is_err()
+            || data
+                .pagetable
+                .copyout(UVAddr::new(addr + older.len()), newer)
+                .is_err()
+        {
+            return usize::MAX;
+        }
+        older.len() + newer.len()
     }
 
     pub unsafe fn sys_poweroff(&self) -> usize {
         let exitcode = ok_or!(argint(0), return usize::MAX);
         poweroff::machine_poweroff(exitcode as _);
     }
+
+    /// Write back the deferred writes of all files, and then power off (RB_POWER_OFF)
+    /// or reset (RB_AUTOBOOT) the machine. Returns only on an unknown cmd.
+    pub unsafe fn sys_reboot(&self) -> usize {
+        let cmd = ok_or!(argint(0), return usize::MAX);
+        if cmd != RB_POWER_OFF && cmd != RB_AUTOBOOT {
+            return usize::MAX;
+        }
+        self.itable.sync(ROOTDEV);
+        if cmd == RB_POWER_OFF {
+            poweroff::machine_poweroff(0);
+        }
+        poweroff::machine_reboot();
+    }
+
+    /// Copy out the resource usage of the current process (RUSAGE_SELF),
+    /// or of its terminated children that it has waited for (RUSAGE_CHILDREN).
+    pub unsafe fn sys_getrusage(&self) -> usize {
+        let who = ok_or!(argint(0), return usize::MAX);
+        let addr = ok_or!(argaddr(1), return usize::MAX);
+        let data = &mut *(*myproc()).data.get();
+        let mut usage = match who {
+            RUSAGE_SELF => data.rusage,
+            RUSAGE_CHILDREN => data.child_rusage,
+            _ => return usize::MAX,
+        };
+        ok_or!(
+            data.pagetable.copyout(
+                UVAddr::new(addr),
+                slice::from_raw_parts_mut(
+                    &mut usage as *mut Rusage as *mut u8,
+                    mem::size_of::<Rusage>(),
+                ),
+            ),
+            return usize::MAX
+        );
+        0
+    }
+
+    /// Copy out the current process's limit on the given resource.
+    pub unsafe fn sys_getrlimit(&self) -> usize {
+        let resource = ok_or!(argint(0), return usize::MAX) as usize;
+        let addr = ok_or!(argaddr(1), return usize::MAX);
+        ok_or!(self.prlimit(resource, None, Some(addr)), return usize::MAX);
+        0
+    }
+
+    /// Set the current process's limit on the given resource.
+    /// The soft limit may not exceed the hard limit,
+    /// and the hard limit may only be lowered.
+    pub unsafe fn sys_setrlimit(&self) -> usize {
+        let resource = ok_or!(argint(0), return usize::MAX) as usize;
+        let addr = ok_or!(argaddr(1), return usize::MAX);
+        ok_or!(self.prlimit(resource, Some(addr), None), return usize::MAX);
+        0
+    }
+
+    /// Copy out the old limit on the given resource unless old_limit is 0, and then
+    /// set the new one unless new_limit is 0, as a single step. pid must be 0 or the
+    /// caller's: rv6 has no credentials to check, and a process's limits are only
+    /// accessed by itself, without a lock.
+    pub unsafe fn sys_prlimit(&self) -> usize {
+        let pid = ok_or!(argint(0), return usize::MAX);
+        let resource = ok_or!(argint(1), return usize::MAX) as usize;
+        let new = ok_or!(argaddr(2), return usize::MAX);
+        let old = ok_or!(argaddr(3), return usize::MAX);
+        if pid != 0 && pid != (*myproc()).pid() {
+            return usize::MAX;
+        }
+        let new = if new == 0 { None } else { Some(new) };
+        let old = if old == 0 { None } else { Some(old) };
+        ok_or!(self.prlimit(resource, new, old), return usize::MAX);
+        0
+    }
+
+    /// Copy out the current process's limit on resource to user address old, if given,
+    /// and then set it to the one at user address new, if given. Checks the new limit
+    /// like setrlimit(), and changes nothing on failure.
+    unsafe fn prlimit(
+        &self,
+        resource: usize,
+        new: Option<usize>,
+        old: Option<usize>,
+    ) -> Result<(), ()> {
+        let data = &mut *(*myproc()).data.get();
+        if resource >= RLIM_NLIMITS {
+            return Err(());
+        }
+        let new = match new {
+            Some(addr) => {
+                let mut rlim = Rlimit::infinity();
+                data.pagetable.copyin(
+                    slice::from_raw_parts_mut(
+                        &mut rlim as *mut Rlimit as *mut u8,
+                        mem::size_of::<Rlimit>(),
+                    ),
+                    UVAddr::new(addr),
+                )?;
+                if rlim.cur > rlim.max || rlim.max > data.rlimits[resource].max {
+                    return Err(());
+                }
+                Some(rlim)
+            }
+            None => None,
+        };
+        if let Some(addr) = old {
+            let mut rlim = data.rlimits[resource];
+            data.pagetable.copyout(
+                UVAddr::new(addr),
+                slice::from_raw_parts_mut(
+                    &mut rlim as *mut Rlimit as *mut u8,
+                    mem::size_of::<Rlimit>(),
+                ),
+            )?;
+        }
+        if let Some(rlim) = new {
+            data.rlimits[resource] = rlim;
+        }
+        Ok(())
+    }
 }
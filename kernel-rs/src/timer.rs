@@ -0,0 +1,160 @@
+//! Timer wheel for processes sleeping for a number of ticks.
+//!
+//! A sleeping process links its Timer into the bucket of its deadline,
+//! so the clock interrupt only examines the bucket of the current tick
+//! instead of waking up every sleeping process.
+
+use core::ptr;
+
+use crate::{
+    list::ListEntry,
+    proc::{myproc, Proc, WaitChannel},
+    sleepablelock::SleepablelockGuard,
+};
+
+/// Number of buckets in the timer wheel.
+const NBUCKET: usize = 32;
+
+/// Per-process timer. Protected by kernel().ticks.
+// It needs repr(C) because `list_entry` must be located at the beginning of `Timer`.
+// See TimerWheel::tick().
+#[repr(C)]
+pub struct Timer {
+    list_entry: ListEntry,
+
+    /// The tick at which the timer expires.
+    deadline: u32,
+
+    /// The process sleeping on the timer.
+    proc: *const Proc,
+
+    waitchannel: WaitChannel,
+}
+
+pub struct TimerWheel {
+    /// Number of clock tick interrupts since start.
+    ticks: u32,
+
+    /// buckets[i] holds timers whose deadline is i modulo NBUCKET.
+    buckets: [ListEntry; NBUCKET],
+}
+
+impl Timer {
+    pub const fn zero() -> Self {
+        Self {
+            list_entry: ListEntry::new(),
+            deadline: 0,
+            proc: ptr::null(),
+            waitchannel: WaitChannel::new(),
+        }
+    }
+}
+
+impl TimerWheel {
+    pub const fn zero() -> Self {
+        const fn bucket_entry(_: usize) -> ListEntry {
+            ListEntry::new()
+        }
+
+        Self {
+            ticks: 0,
+            buckets: array![x => bucket_entry(x); NBUCKET],
+        }
+    }
+
+    pub fn init(&mut self) {
+        for bucket in &mut self.buckets {
+            bucket.init();
+        }
+    }
+
+    pub fn ticks(&self) -> u32 {
+        self.ticks
+    }
+
+    /// Advance the clock and wake up processes whose timers expired.
+    pub fn tick(&mut self) {
+        self.advance(|timer| unsafe { (*timer.proc).wakeup_on(&timer.waitchannel) });
+    }
+
+    /// Advance the clock, and unlink each expired timer and pass it to wake.
+    /// Only the bucket of the new tick is examined.
+    /// Returns the number of timers examined.
+    fn advance<F: FnMut(&Timer)>(&mut self, mut wake: F) -> usize {
+        self.ticks = self.ticks.wrapping_add(1);
+        let head = &self.buckets[self.ticks as usize % NBUCKET];
+        let mut list_entry = head.next();
+        let mut examined = 0;
+        while list_entry as *const _ != head as *const _ {
+            let timer = unsafe { &mut *(list_entry as *const _ as usize as *mut Timer) };
+            list_entry = list_entry.next();
+            examined += 1;
+            if timer.deadline == self.ticks {
+                timer.list_entry.remove();
+                wake(timer);
+            }
+        }
+        examined
+    }
+
+    /// Link timer into the bucket of its deadline, `ticks` ticks from now.
+    fn add(&mut self, timer: &mut Timer, ticks: u32) {
+        timer.deadline = self.ticks.wrapping_add(ticks);
+        self.buckets[timer.deadline as usize % NBUCKET].append(&mut timer.list_entry);
+    }
+}
+
+impl SleepablelockGuard<'_, TimerWheel> {
+    /// Sleep until `ticks` ticks have passed, or until woken up by some other event.
+    /// The caller must recheck its condition after this returns.
+    pub unsafe fn sleep_timer(&mut self, timer: &mut Timer, ticks: u32) {
+        timer.proc = myproc();
+        self.add(timer, ticks);
+        timer.waitchannel.sleep_sleepable(self);
+
+        // If we were woken up for another reason (e.g. kill), the timer is still linked.
+        timer.list_entry.remove();
+    }
+}
+
+/// Drive a timer wheel with a mock clock, and check that timers with different
+/// deadlines, some sharing a bucket or a deadline, each expire exactly at their
+/// deadline, and that each tick examines only the timers in its own bucket.
+/// Run at boot when the kernel is built with the `test` feature.
+#[cfg(feature = "test")]
+pub fn timer_selftest() {
+    const DEADLINES: [u32; 7] = [3, 3, 35, 67, 10, 42, 31];
+
+    let mut wheel = TimerWheel::zero();
+    wheel.init();
+    let mut timers = [
+        Timer::zero(),
+        Timer::zero(),
+        Timer::zero(),
+        Timer::zero(),
+        Timer::zero(),
+        Timer::zero(),
+        Timer::zero(),
+    ];
+    for (timer, deadline) in timers.iter_mut().zip(DEADLINES.iter()) {
+        wheel.add(timer, *deadline);
+    }
+
+    let mut expired = [0; DEADLINES.len()];
+    for now in 1..=70 {
+        let pending = DEADLINES
+            .iter()
+            .zip(expired.iter())
+            .filter(|(d, e)| **e == 0 && **d as usize % NBUCKET == now as usize % NBUCKET)
+            .count();
+        let examined = wheel.advance(|timer| {
+            let i = timers
+                .iter()
+                .position(|t| t as *const _ == timer as *const _)
+                .unwrap();
+            expired[i] = now;
+        });
+        assert_eq!(examined, pending, "timer_selftest: examined another bucket");
+    }
+    assert_eq!(expired, DEADLINES, "timer_selftest: wrong expiry");
+}
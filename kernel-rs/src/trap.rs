@@ -88,6 +88,7 @@ pub unsafe extern "C" fn usertrap() {
 
     // Give up the CPU if this is a timer interrupt.
     if which_dev == 2 {
+        (*(*p).data.get()).rusage.utime += 1;
         proc_yield();
     }
 
@@ -177,6 +178,7 @@ pub unsafe fn kerneltrap() {
 
     // Give up the CPU if this is a timer interrupt.
     if which_dev == 2 && !myproc().is_null() && (*myproc()).state() == Procstate::RUNNING {
+        (*(*myproc()).data.get()).rusage.stime += 1;
         proc_yield();
     }
 
@@ -187,9 +189,7 @@ pub unsafe fn kerneltrap() {
 }
 
 pub unsafe fn clockintr() {
-    let mut ticks = kernel().ticks.lock();
-    *ticks = ticks.wrapping_add(1);
-    ticks.wakeup();
+    kernel().tick();
 }
 
 /// Check if it's an external interrupt or software interrupt,
@@ -198,6 +198,38 @@ pub unsafe fn clockintr() {
 /// 1 if other device,
 /// 0 if not recognized.
 pub unsafe fn devintr() -> i32 {
+    // The handlers must never sleep; WaitChannel::sleep_raw() checks this in debug builds.
+    in_devintr(|| handle_devintr())
+}
+
+/// Run f as a device interrupt handler, during which may_sleep() is false.
+unsafe fn in_devintr<R, F: FnOnce() -> R>(f: F) -> R {
+    let cpu = kernel().mycpu();
+    (*cpu).in_devintr = true;
+    let r = f();
+    (*cpu).in_devintr = false;
+    r
+}
+
+/// Check that may_sleep(), which WaitChannel::sleep_raw() asserts, is false in
+/// device interrupt handlers and true otherwise.
+/// Run at boot when the kernel is built with the `test` feature.
+#[cfg(feature = "test")]
+pub unsafe fn devintr_selftest() {
+    use crate::{
+        proc::may_sleep,
+        spinlock::{pop_off, push_off},
+    };
+
+    push_off();
+    assert!(may_sleep());
+    in_devintr(|| assert!(!may_sleep()));
+    assert!(may_sleep());
+    pop_off();
+}
+
+/// devintr() without the may_sleep() bookkeeping.
+unsafe fn handle_devintr() -> i32 {
     let scause: usize = r_scause();
 
     if scause & 0x8000000000000000 != 0 && scause & 0xff == 9 {
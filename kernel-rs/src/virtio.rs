@@ -129,6 +129,16 @@ pub const VIRTIO_BLK_T_IN: u32 = 0;
 /// write the disk
 pub const VIRTIO_BLK_T_OUT: u32 = 1;
 
+/// read the device id string
+pub const VIRTIO_BLK_T_GET_ID: u32 = 8;
+
+/// Length of the device id string. Shorter ids are padded with NULs.
+pub const VIRTIO_BLK_ID_BYTES: usize = 20;
+
+/// VirtqUsed::flags: the device is still processing the avail ring,
+/// so the driver need not notify it of new entries.
+pub const VRING_USED_F_NO_NOTIFY: u16 = 1;
+
 // It needs repr(C) because it's struct for in-disk representation
 // which should follow C(=machine) representation
 // https://github.com/kaist-cp/rv6/issues/52
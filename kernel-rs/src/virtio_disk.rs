@@ -2,21 +2,25 @@
 /// Uses qemu's mmio interface to virtio.
 /// qemu presents a "legacy" virtio interface.
 ///
-/// qemu ... -drive file=fs.img,if=none,format=raw,id=x0 -device virtio-blk-device,drive=x0,bus=virtio-mmio-bus.0
+/// qemu ... -drive file=fs.img,if=none,format=raw,id=x0 -device virtio-blk-device,drive=x0,bus=virtio-mmio-bus.0,serial=rv6-fs.img
 use crate::{
     bio::Buf,
+    file::Devsw,
     kernel::kernel,
     page::RawPage,
-    param::BSIZE,
+    param::{BSIZE, NDEV},
+    proc::{myproc, WaitChannel},
     riscv::{PGSHIFT, PGSIZE},
     sleepablelock::{Sleepablelock, SleepablelockGuard},
     virtio::*,
+    vm::{UVAddr, VAddr},
 };
 
 use core::array::IntoIter;
 use core::mem;
 use core::ops::{Deref, DerefMut};
 use core::ptr;
+use core::slice;
 use core::sync::atomic::{fence, Ordering};
 
 use arrayvec::ArrayVec;
@@ -36,6 +40,62 @@ pub struct Disk {
     /// Disk command headers.
     /// One-for-one with descriptors, for convenience.
     ops: [VirtIOBlockOutHeader; NUM],
+
+    /// Recently submitted and completed requests, for debugging.
+    trace: DiskTrace,
+
+    /// Number of requests made available to the device.
+    requests: usize,
+
+    /// Number of QueueNotify writes. Less than `requests` if the device
+    /// suppressed some notifications.
+    notifies: usize,
+}
+
+/// Major device number of the disk trace, which read()s out the records of `DiskTrace`.
+const DISKTRACE_DEVSW: usize = 2;
+
+/// Major device number of the disk id, which read()s out the device id string.
+const DISKID_DEVSW: usize = 3;
+
+/// Number of records kept in the disk trace.
+const NTRACE: usize = 64;
+
+/// TraceRecord::event: the request was handed to the device.
+const TRACE_SUBMIT: u32 = 0;
+
+/// TraceRecord::event: the device completed the request.
+const TRACE_COMPLETE: u32 = 1;
+
+// It needs repr(C) because it's copied out to user space,
+// and should follow the layout of struct disktrace in kernel/disktrace.h.
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct TraceRecord {
+    /// Clock ticks when the event happened
+    ticks: u32,
+
+    /// TRACE_SUBMIT or TRACE_COMPLETE
+    event: u32,
+
+    /// 1 for a write, 0 for a read
+    write: u32,
+
+    /// Index of the first descriptor of the request
+    desc: u32,
+
+    sector: u64,
+}
+
+/// Ring buffer of TraceRecords. When full, the oldest record is overwritten.
+struct DiskTrace {
+    records: [TraceRecord; NTRACE],
+
+    /// Number of records ever added.
+    head: usize,
+
+    /// Number of records ever read out, or skipped because they were overwritten.
+    tail: usize,
 }
 
 struct DescriptorPool {
@@ -72,7 +132,10 @@ struct VirtqAvail {
 
 #[derive(Copy, Clone)]
 struct InflightInfo {
-    b: *mut Buf<'static>,
+    /// Cleared by virtio_intr() when the device is done with the request.
+    pending: *mut bool,
+    /// Woken up by virtio_intr() when the device is done with the request.
+    waitchannel: *const WaitChannel,
     status: bool,
 }
 
@@ -99,13 +162,7 @@ impl VirtIOBlockOutHeader {
         }
     }
 
-    fn new(write: bool, sector: usize) -> Self {
-        let typ = if write {
-            VIRTIO_BLK_T_OUT
-        } else {
-            VIRTIO_BLK_T_IN
-        };
-
+    fn new(typ: u32, sector: usize) -> Self {
         Self {
             typ,
             reserved: 0,
@@ -215,6 +272,10 @@ impl Sleepablelock<Disk> {
         let mut buf = kernel().bcache.get_buf(dev, blockno).lock();
         if !buf.deref_inner().valid {
             unsafe {
+                let p = myproc();
+                if !p.is_null() {
+                    (*(*p).data.get()).rusage.inblock += 1;
+                }
                 Disk::virtio_rw(&mut self.lock(), &mut buf, false);
             }
             buf.deref_mut_inner().valid = true;
@@ -223,7 +284,13 @@ impl Sleepablelock<Disk> {
     }
 
     pub fn write(&self, b: &mut Buf<'static>) {
-        unsafe { Disk::virtio_rw(&mut self.lock(), b, true) }
+        unsafe {
+            let p = myproc();
+            if !p.is_null() {
+                (*(*p).data.get()).rusage.oublock += 1;
+            }
+            Disk::virtio_rw(&mut self.lock(), b, true)
+        }
     }
 }
 
@@ -236,6 +303,9 @@ impl Disk {
             used_idx: 0,
             info: [InflightInfo::zero(); NUM],
             ops: [VirtIOBlockOutHeader::zero(); NUM],
+            trace: DiskTrace::zero(),
+            requests: 0,
+            notifies: 0,
         }
     }
 
@@ -245,7 +315,51 @@ impl Disk {
         write: bool,
     ) {
         let sector: usize = (*b).blockno.wrapping_mul((BSIZE / 512) as u32) as _;
+        let typ = if write {
+            VIRTIO_BLK_T_OUT
+        } else {
+            VIRTIO_BLK_T_IN
+        };
+        let data = b.deref_mut_inner().data.as_mut_ptr();
+        let pending = &mut b.deref_mut_inner().disk as *mut bool;
+        Self::submit(
+            this,
+            VirtIOBlockOutHeader::new(typ, sector),
+            data,
+            BSIZE,
+            pending,
+            &b.vdisk_request_waitchannel,
+        );
+    }
+
+    /// Read the device id string. The device pads ids shorter than
+    /// VIRTIO_BLK_ID_BYTES with NULs.
+    pub unsafe fn get_id(this: &mut SleepablelockGuard<'_, Self>) -> [u8; VIRTIO_BLK_ID_BYTES] {
+        let mut id = [0; VIRTIO_BLK_ID_BYTES];
+        let mut pending = false;
+        let waitchannel = WaitChannel::new();
+        Self::submit(
+            this,
+            VirtIOBlockOutHeader::new(VIRTIO_BLK_T_GET_ID, 0),
+            id.as_mut_ptr(),
+            VIRTIO_BLK_ID_BYTES,
+            &mut pending,
+            &waitchannel,
+        );
+        id
+    }
 
+    /// Submit a request whose data is the `len` bytes at `data`, and sleep on
+    /// `waitchannel` until virtio_intr() clears `*pending`. The device writes the data
+    /// unless `header` is a VIRTIO_BLK_T_OUT request.
+    unsafe fn submit(
+        this: &mut SleepablelockGuard<'_, Self>,
+        header: VirtIOBlockOutHeader,
+        data: *mut u8,
+        len: usize,
+        pending: *mut bool,
+        waitchannel: *const WaitChannel,
+    ) {
         // The spec's Section 5.2 says that legacy block operations use
         // three descriptors: one for type/reserved/sector, one for the
         // data, one for a 1-byte status result.
@@ -265,7 +379,7 @@ impl Disk {
         // qemu's virtio-blk.c reads them.
 
         let buf0 = &mut this.ops[desc[0].idx] as *mut VirtIOBlockOutHeader;
-        *buf0 = VirtIOBlockOutHeader::new(write, sector);
+        *buf0 = header;
 
         *desc[0] = VirtqDesc {
             addr: buf0 as _,
@@ -274,11 +388,11 @@ impl Disk {
             next: desc[1].idx as _,
         };
 
-        // Device reads/writes b->data
+        // Device reads/writes the data
         *desc[1] = VirtqDesc {
-            addr: b.deref_mut_inner().data.as_mut_ptr() as _,
-            len: BSIZE as _,
-            flags: if write {
+            addr: data as _,
+            len: len as _,
+            flags: if header.typ == VIRTIO_BLK_T_OUT {
                 VirtqDescFlags::NEXT
             } else {
                 VirtqDescFlags::NEXT | VirtqDescFlags::WRITE
@@ -297,9 +411,10 @@ impl Disk {
             next: 0,
         };
 
-        // Record struct Buf for virtio_disk_intr().
-        b.deref_mut_inner().disk = true;
-        this.info[desc[0].idx].b = b;
+        // Record where to report completion for virtio_disk_intr().
+        *pending = true;
+        this.info[desc[0].idx].pending = pending;
+        this.info[desc[0].idx].waitchannel = waitchannel;
 
         // Tell the device the first index in our chain of descriptors.
         let ring_idx = (*this.avail).idx as usize % NUM;
@@ -312,18 +427,66 @@ impl Disk {
 
         fence(Ordering::SeqCst);
 
-        // Value is queue number.
-        MmioRegs::QueueNotify.write(0);
+        this.notify();
+
+        let header = this.ops[desc[0].idx];
+        this.trace.push(TRACE_SUBMIT, &header, desc[0].idx);
 
         // Wait for virtio_disk_intr() to say request has finished.
-        while b.deref_mut_inner().disk {
-            (*b).vdisk_request_waitchannel.sleep_sleepable(this);
+        // The disk lock is held from the check of *pending until sleep_sleepable()
+        // acquires p->lock, and virtio_intr() runs with the disk lock held, so
+        // the completion cannot slip in between the check and the sleep.
+        while ptr::read_volatile(pending) {
+            (*waitchannel).sleep_sleepable(this);
         }
-        this.info[desc[0].idx].b = ptr::null_mut();
+        this.info[desc[0].idx].pending = ptr::null_mut();
+        this.info[desc[0].idx].waitchannel = ptr::null();
         IntoIter::new(desc).for_each(|desc| this.desc.free(desc));
         this.wakeup();
     }
 
+    /// Tell the device that another avail ring entry is available.
+    ///
+    /// Skip the notification if the device asks us to. It sets the flag only while
+    /// it is walking the avail ring, and checks avail.idx again after clearing the
+    /// flag, so it cannot miss the entry. The caller's fence orders its update of
+    /// avail.idx before this read. We never set VRING_AVAIL_F_NO_INTERRUPT, since
+    /// we always sleep until the completion interrupt.
+    unsafe fn notify(&mut self) {
+        self.requests += 1;
+        if ptr::read_volatile(&(*self.used)[0].flags) & VRING_USED_F_NO_NOTIFY == 0 {
+            // Value is queue number.
+            MmioRegs::QueueNotify.write(0);
+            self.notifies += 1;
+        }
+    }
+
+    /// Check that notify() skips QueueNotify while VRING_USED_F_NO_NOTIFY is set, by
+    /// setting the flag as the device would. Notifying without a new avail ring entry
+    /// is harmless. Run at boot with the `test` feature, while no request is in flight.
+    #[cfg(feature = "test")]
+    pub unsafe fn notify_selftest(&mut self) {
+        let flags = &mut (*self.used)[0].flags as *mut u16;
+        let saved = ptr::read_volatile(flags);
+        let (requests, notifies) = (self.requests, self.notifies);
+
+        ptr::write_volatile(flags, saved | VRING_USED_F_NO_NOTIFY);
+        for _ in 0..4 {
+            self.notify();
+        }
+        assert_eq!(self.notifies, notifies);
+
+        ptr::write_volatile(flags, saved & !VRING_USED_F_NO_NOTIFY);
+        self.notify();
+        ptr::write_volatile(flags, saved);
+
+        assert_eq!(self.requests - requests, 5);
+        assert_eq!(self.notifies - notifies, 1);
+    }
+
+    /// Handle a disk interrupt. Called from devintr() in interrupt context,
+    /// so it must never sleep: Sleepablelock::lock() only spins, and this takes
+    /// `&mut Disk` rather than the guard so that it cannot sleep on the disk lock.
     pub unsafe fn virtio_intr(&mut self) {
         // The device won't raise another interrupt until we tell it
         // we've seen this interrupt, which the following line does.
@@ -344,26 +507,111 @@ impl Disk {
 
             assert!(!self.info[id].status, "virtio_self_intr status");
 
-            let buf = &mut *self.info[id].b;
+            // disk is done with the request
+            *self.info[id].pending = false;
+            (*self.info[id].waitchannel).wakeup();
 
-            // disk is done with buf
-            buf.deref_mut_inner().disk = false;
-            buf.vdisk_request_waitchannel.wakeup();
+            let header = self.ops[id];
+            self.trace.push(TRACE_COMPLETE, &header, id);
 
             self.used_idx += 1;
         }
     }
 }
 
+impl TraceRecord {
+    const fn zero() -> Self {
+        Self {
+            ticks: 0,
+            event: 0,
+            write: 0,
+            desc: 0,
+            sector: 0,
+        }
+    }
+}
+
+impl DiskTrace {
+    const fn zero() -> Self {
+        Self {
+            records: [TraceRecord::zero(); NTRACE],
+            head: 0,
+            tail: 0,
+        }
+    }
+
+    fn push(&mut self, event: u32, header: &VirtIOBlockOutHeader, desc: usize) {
+        self.records[self.head % NTRACE] = TraceRecord {
+            ticks: kernel().ticks_snapshot(),
+            event,
+            write: (header.typ == VIRTIO_BLK_T_OUT) as u32,
+            desc: desc as u32,
+            sector: header.sector as u64,
+        };
+        self.head += 1;
+    }
+
+    /// Copy out as many whole unread records as fit in n bytes, oldest first.
+    /// Returns the number of bytes copied, or -1 if the copy failed.
+    unsafe fn read(&mut self, dst: UVAddr, n: i32) -> i32 {
+        let size = mem::size_of::<TraceRecord>();
+        self.tail = self.tail.max(self.head.saturating_sub(NTRACE));
+        let mut copied = 0;
+        while self.tail != self.head && copied + size <= n as usize {
+            let record = &self.records[self.tail % NTRACE];
+            let src = slice::from_raw_parts(record as *const TraceRecord as *const u8, size);
+            if UVAddr::copyout(dst + copied, src).is_err() {
+                return -1;
+            }
+            copied += size;
+            self.tail += 1;
+        }
+        copied as i32
+    }
+}
+
 impl InflightInfo {
     const fn zero() -> Self {
         Self {
-            b: ptr::null_mut(),
+            pending: ptr::null_mut(),
+            waitchannel: ptr::null(),
             status: false,
         }
     }
 }
 
+pub unsafe fn disktraceinit(devsw: &mut [Devsw; NDEV]) {
+    devsw[DISKTRACE_DEVSW] = Devsw {
+        read: Some(disktraceread),
+        write: None,
+    };
+}
+
+pub unsafe fn diskidinit(devsw: &mut [Devsw; NDEV]) {
+    devsw[DISKID_DEVSW] = Devsw {
+        read: Some(diskidread),
+        write: None,
+    };
+}
+
+/// User read()s from the disk id go here.
+/// Devices have no file offset, so every read starts from the
+/// beginning of the id and copies out at most n bytes of it.
+unsafe fn diskidread(dst: UVAddr, n: i32) -> i32 {
+    let id = Disk::get_id(&mut kernel().disk.lock());
+    let n = (n.max(0) as usize).min(id.len());
+    if UVAddr::copyout(dst, &id[..n]).is_err() {
+        return -1;
+    }
+    n as i32
+}
+
+/// User read()s from the disk trace go here.
+/// Does not wait for new records: returns 0 if there are none.
+unsafe fn disktraceread(dst: UVAddr, n: i32) -> i32 {
+    kernel().disk.lock().trace.read(dst, n)
+}
+
 pub unsafe fn virtio_disk_init(virtqueue: &mut [RawPage; 2], disk: &mut Disk) {
     let mut status: VirtIOStatus = VirtIOStatus::empty();
     assert!(
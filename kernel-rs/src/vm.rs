@@ -1,6 +1,7 @@
 use crate::{
     kernel::kernel,
     memlayout::{FINISHER, KERNBASE, PHYSTOP, PLIC, TRAMPOLINE, UART0, VIRTIO0},
+    mman::{PROT_EXEC, PROT_READ, PROT_WRITE},
     page::{Page, RawPage},
     proc::{myproc, proc_mapstacks},
     riscv::{
@@ -13,7 +14,7 @@ use core::{
     marker::PhantomData,
     mem,
     ops::{Add, Deref, DerefMut},
-    ptr,
+    ptr, slice,
 };
 
 extern "C" {
@@ -343,6 +344,22 @@ impl<A: VAddr> PageTable<A> {
         Ok(())
     }
 
+    /// Like walkaddr, but the page must also be writable,
+    /// so the kernel honors mprotect() on behalf of the user.
+    unsafe fn walkaddr_writable(&mut self, va: A) -> Option<PAddr> {
+        if va.into_usize() >= MAXVA {
+            return None;
+        }
+        let pte = self.walk(va, 0)?;
+        if !pte.check_flag(PTE_V)
+            || !pte.check_flag(PTE_U as usize)
+            || !pte.check_flag(PTE_W as usize)
+        {
+            return None;
+        }
+        Some(pte.get_pa())
+    }
+
     /// Copy from kernel to user.
     /// Copy len bytes from src to virtual address dstva in a given page table.
     /// Return Ok(()) on success, Err(()) on error.
@@ -352,7 +369,10 @@ impl<A: VAddr> PageTable<A> {
         let mut offset = 0;
         while len > 0 {
             let va0 = pgrounddown(dst);
-            let pa0 = self.walkaddr(VAddr::new(va0)).ok_or(())?.into_usize();
+            let pa0 = self
+                .walkaddr_writable(VAddr::new(va0))
+                .ok_or(())?
+                .into_usize();
             let mut n = PGSIZE - (dst - va0);
             if n > len {
                 n = len
@@ -511,6 +531,56 @@ impl PageTable<UVAddr> {
             .clear_flag(PTE_U as usize);
     }
 
+    /// Set the user permissions of the pages in [va, va + len) to prot, a combination of
+    /// PROT_READ, PROT_WRITE and PROT_EXEC. All user pages are mapped readable, writable
+    /// and executable, so any prot is allowed, but it must include PROT_READ: RISC-V has
+    /// no write-only pages, and a leaf PTE without R, W and X would be a page table.
+    /// va must be page-aligned, and the pages must be mapped for user access below sz.
+    /// Changes nothing on failure.
+    pub unsafe fn mprotect(
+        &mut self,
+        va: UVAddr,
+        len: usize,
+        prot: i32,
+        sz: usize,
+    ) -> Result<(), ()> {
+        let start = va.into_usize();
+        let end = start.checked_add(len).ok_or(())?;
+        if start % PGSIZE != 0 || end > sz {
+            return Err(());
+        }
+        if prot & PROT_READ == 0 || prot & !(PROT_READ | PROT_WRITE | PROT_EXEC) != 0 {
+            return Err(());
+        }
+        let end = pgroundup(end);
+
+        // Check the whole range first, so that nothing changes on failure.
+        for a in num_iter::range_step(start, end, PGSIZE) {
+            let pte = self.walk(UVAddr::new(a), 0).ok_or(())?;
+            if !pte.check_flag(PTE_V) || !pte.check_flag(PTE_U as usize) {
+                return Err(());
+            }
+        }
+
+        let mut perm = PTE_R;
+        if prot & PROT_WRITE != 0 {
+            perm |= PTE_W;
+        }
+        if prot & PROT_EXEC != 0 {
+            perm |= PTE_X;
+        }
+        for a in num_iter::range_step(start, end, PGSIZE) {
+            let pte = self.walk(UVAddr::new(a), 0).expect("mprotect: walk");
+            pte.clear_flag((PTE_R | PTE_W | PTE_X) as usize);
+            pte.set_flag(perm as usize);
+        }
+
+        // Only this hart can have cached the old permissions: the process is running
+        // here, and every switch to a user page table flushes the TLB.
+        sfence_vma();
+        Ok(())
+    }
+
     /// Copy from user to kernel.
     /// Copy len bytes to dst from virtual address srcva in a given page table.
     /// Return Ok(()) on success, Err(()) on error.
@@ -537,10 +607,21 @@ impl PageTable<UVAddr> {
         Ok(())
     }
 
+    /// Copy a contiguous user array at virtual address srcva to dst,
+    /// walking the page table once per page rather than once per element.
+    /// Return Ok(()) on success, Err(()) on error.
+    pub unsafe fn copyin_array<T: Copy>(&mut self, dst: &mut [T], srcva: UVAddr) -> Result<(), ()> {
+        self.copyin(
+            slice::from_raw_parts_mut(dst.as_mut_ptr() as *mut u8, mem::size_of_val(dst)),
+            srcva,
+        )
+    }
+
     /// Copy a null-terminated string from user to kernel.
     /// Copy bytes to dst from virtual address srcva in a given page table,
     /// until a '\0', or max.
-    /// Return OK(()) on success, Err(()) on error.
+    /// Return OK(()) on success, Err(()) on error, including when
+    /// there is no '\0' within dst.len() bytes.
     pub unsafe fn copyinstr(&mut self, dst: &mut [u8], srcva: UVAddr) -> Result<(), ()> {
         let mut got_null: i32 = 0;
         let mut src = srcva.into_usize();
@@ -0,0 +1,3 @@
+#define DISKID          3  // Major device number of the disk id
+
+#define DISKID_BYTES    20 // Length of the id; shorter ids are padded with NULs
@@ -0,0 +1,13 @@
+#define DISKTRACE       2  // Major device number of the disk trace
+
+#define TRACE_SUBMIT    0  // The request was handed to the device
+#define TRACE_COMPLETE  1  // The device completed the request
+
+// Records read() from the DISKTRACE device.
+struct disktrace {
+  uint ticks;    // Clock ticks when the event happened
+  uint event;    // TRACE_SUBMIT or TRACE_COMPLETE
+  uint write;    // 1 for a write, 0 for a read
+  uint desc;     // Index of the first descriptor of the request
+  uint64 sector;
+};
@@ -0,0 +1,3 @@
+#define PROT_READ   0x1  // Pages may be read
+#define PROT_WRITE  0x2  // Pages may be written
+#define PROT_EXEC   0x4  // Pages may be executed
@@ -0,0 +1,2 @@
+#define RB_POWER_OFF  0x4321fedc  // Power off the machine
+#define RB_AUTOBOOT   0x01234567  // Reset the machine
@@ -0,0 +1,22 @@
+#define RUSAGE_SELF      0   // The calling process
+#define RUSAGE_CHILDREN  (-1) // Terminated and waited-for children
+
+struct rusage {
+  uint64 utime;   // Clock ticks spent in user mode
+  uint64 stime;   // Clock ticks spent in the kernel
+  uint64 inblock; // Blocks read from disk
+  uint64 oublock; // Blocks written to disk
+  uint64 nvcsw;   // Voluntary context switches
+  uint64 nivcsw;  // Involuntary context switches
+};
+
+#define RLIMIT_NOFILE  0   // Maximum number of open file descriptors
+#define RLIMIT_AS      1   // Maximum size of process memory in bytes
+#define RLIMIT_FSIZE   2   // Maximum size of a written file in bytes
+
+#define RLIM_INFINITY  (~0UL) // No limit
+
+struct rlimit {
+  uint64 cur;  // Soft limit, which is enforced
+  uint64 max;  // Hard limit, the ceiling for cur
+};
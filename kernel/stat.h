@@ -1,11 +1,25 @@
 #define T_DIR     1   // Directory
 #define T_FILE    2   // File
 #define T_DEVICE  3   // Device
+#define T_FIFO    4   // Named pipe
 
 struct stat {
   int dev;     // File system's disk device
   uint ino;    // Inode number
   short type;  // Type of file
   short nlink; // Number of links to file
+  uint rdev;   // Device a device file refers to, made by MKDEV
   uint64 size; // Size of file in bytes
 };
+
+#define MKDEV(major, minor) (((uint)(major) << 16) | (uint)(minor))
+#define MAJOR(rdev)         ((rdev) >> 16)
+#define MINOR(rdev)         ((rdev) & 0xffff)
+
+struct statfs {
+  uint bsize;  // Block size in bytes
+  uint blocks; // Number of data blocks
+  uint bfree;  // Number of free data blocks
+  uint files;  // Number of inodes
+  uint ffree;  // Number of free inodes
+};
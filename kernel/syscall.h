@@ -21,3 +21,21 @@
 #define SYS_mkdir  20
 #define SYS_close  21
 #define SYS_poweroff    22
+#define SYS_getrusage   23
+#define SYS_getrlimit   24
+#define SYS_setrlimit   25
+#define SYS_statfs      26
+#define SYS_execve      27
+#define SYS_rmdir       28
+#define SYS_mkfifo      29
+#define SYS_chroot      30
+#define SYS_reboot      31
+#define SYS_mprotect    32
+#define SYS_close_range 33
+#define SYS_prlimit     34
+#define SYS_sched_setaffinity 35
+#define SYS_sched_getaffinity 36
+#define SYS_getcpu      37
+#define SYS_splice      38
+#define SYS_fsck        39
+#define SYS_dmesg       40
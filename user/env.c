@@ -0,0 +1,16 @@
+#include "kernel/types.h"
+#include "kernel/stat.h"
+#include "user/user.h"
+
+// print the environment, one variable per line.
+int
+main(int argc, char *argv[], char *envp[])
+{
+  int i;
+
+  for(i = 0; envp[i]; i++){
+    write(1, envp[i], strlen(envp[i]));
+    write(1, "\n", 1);
+  }
+  exit(0);
+}
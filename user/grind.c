@@ -100,9 +100,11 @@ go(int which_child)
       unlink("b/b");
     } else if(what == 11){
       unlink("b");
+      rmdir("b");
       link("../grindir/./../a", "../b");
     } else if(what == 12){
       unlink("../grindir/../a");
+      rmdir("../grindir/../a");
       link(".././b", "/grindir/../a");
     } else if(what == 13){
       int pid = fork();
@@ -181,9 +183,10 @@ go(int which_child)
       int pid = fork();
       if(pid == 0){
         unlink("a");
+        rmdir("a");
         mkdir("a");
         chdir("a");
-        unlink("../a");
+        rmdir("../a");
         fd = open("x", O_CREATE|O_RDWR);
         unlink("x");
         exit(0);
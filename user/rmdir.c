@@ -0,0 +1,23 @@
+#include "kernel/types.h"
+#include "kernel/stat.h"
+#include "user/user.h"
+
+int
+main(int argc, char *argv[])
+{
+  int i;
+
+  if(argc < 2){
+    fprintf(2, "Usage: rmdir dirs...\n");
+    exit(1);
+  }
+
+  for(i = 1; i < argc; i++){
+    if(rmdir(argv[i]) < 0){
+      fprintf(2, "rmdir: %s failed to delete\n", argv[i]);
+      break;
+    }
+  }
+
+  exit(0);
+}
@@ -0,0 +1,21 @@
+#include "kernel/types.h"
+#include "kernel/stat.h"
+#include "kernel/reboot.h"
+#include "user/user.h"
+
+int
+main(int argc, char *argv[])
+{
+  int cmd = RB_POWER_OFF;
+
+  if(argc == 2 && strcmp(argv[1], "-r") == 0){
+    cmd = RB_AUTOBOOT;
+  } else if(argc != 1){
+    fprintf(2, "Usage: shutdown [-r]\n");
+    exit(1);
+  }
+
+  reboot(cmd);
+  fprintf(2, "shutdown: failed\n");
+  exit(1);
+}
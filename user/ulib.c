@@ -76,7 +76,8 @@ stat(const char *n, struct stat *st)
   int fd;
   int r;
 
-  fd = open(n, O_RDONLY);
+  // O_NONBLOCK so that a FIFO is not waited on.
+  fd = open(n, O_RDONLY|O_NONBLOCK);
   if(fd < 0)
     return -1;
   r = fstat(fd, st);
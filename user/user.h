@@ -1,5 +1,8 @@
 struct stat;
 struct rtcdate;
+struct rusage;
+struct rlimit;
+struct statfs;
 
 // system calls
 int fork(void);
@@ -9,11 +12,17 @@ int pipe(int*);
 int write(int, const void*, int);
 int read(int, void*, int);
 int close(int);
+int close_range(uint, uint, int);
+int splice(int, uint64*, int, uint64*, int, int);
 int kill(int);
 int exec(char*, char**);
+int execve(char*, char**, char**);
 int open(const char*, int);
 int mknod(const char*, short, short);
 int unlink(const char*);
+int rmdir(const char*);
+int mkfifo(const char*);
+int chroot(const char*);
 int fstat(int fd, struct stat*);
 int link(const char*, const char*);
 int mkdir(const char*);
@@ -21,9 +30,21 @@ int chdir(const char*);
 int dup(int);
 int getpid(void);
 char* sbrk(int);
+int mprotect(void*, uint64, int);
 int sleep(int);
 int uptime(void);
+int dmesg(char*, int);
+int sched_setaffinity(int, uint64);
+int sched_getaffinity(int);
+int getcpu(void);
 int poweroff(int) __attribute__((noreturn));
+int reboot(int);
+int getrusage(int, struct rusage*);
+int getrlimit(int, struct rlimit*);
+int setrlimit(int, const struct rlimit*);
+int prlimit(int, int, const struct rlimit*, struct rlimit*);
+int statfs(const char*, struct statfs*);
+int fsck(void);
 
 // ulib.c
 int stat(const char*, struct stat*);
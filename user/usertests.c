@@ -7,6 +7,10 @@
 #include "kernel/syscall.h"
 #include "kernel/memlayout.h"
 #include "kernel/riscv.h"
+#include "kernel/resource.h"
+#include "kernel/disktrace.h"
+#include "kernel/diskid.h"
+#include "kernel/mman.h"
 
 //
 // Tests xv6 system calls.  usertests without arguments runs them all
@@ -161,6 +165,19 @@ copyinstr2(char *s)
     exit(1);
   }
 
+  // the longest path that fits, including its nul, is fine.
+  for(int i = 0; i < MAXPATH-1; i += 2){
+    b[i] = '.';
+    b[i+1] = '/';
+  }
+  b[MAXPATH-2] = '.';
+  b[MAXPATH-1] = '\0';
+  ret = chdir(b);
+  if(ret != 0){
+    printf("chdir(%s) returned %d, not 0\n", b, ret);
+    exit(1);
+  }
+
   int pid = fork();
   if(pid < 0){
     printf("fork failed\n");
@@ -412,6 +429,78 @@ truncate3(char *s)
   unlink("truncfile");
   exit(xstatus);
 }
+
+// 16 one-byte writes within a block are combined: they write
+// nothing to the disk, are visible through another fd, and cost
+// one block write, like a single 16-byte write, on close.
+void
+writecombine(char *s)
+{
+  struct rusage r0, r1;
+  char buf[32];
+  int fd1, fd2, i, n;
+  long one;
+
+  unlink("wcfile");
+  fd1 = open("wcfile", O_CREATE|O_WRONLY);
+  if(fd1 < 0 || write(fd1, "................", 16) != 16){
+    printf("%s: create wcfile failed\n", s);
+    exit(1);
+  }
+  getrusage(RUSAGE_SELF, &r0);
+  close(fd1);
+  getrusage(RUSAGE_SELF, &r1);
+  one = r1.oublock - r0.oublock;
+  if(one == 0){
+    printf("%s: close wrote nothing\n", s);
+    exit(1);
+  }
+
+  fd1 = open("wcfile", O_WRONLY);
+  if(fd1 < 0){
+    printf("%s: open wcfile failed\n", s);
+    exit(1);
+  }
+  getrusage(RUSAGE_SELF, &r0);
+  for(i = 0; i < 16; i++){
+    buf[0] = 'a' + i;
+    if(write(fd1, buf, 1) != 1){
+      printf("%s: write failed\n", s);
+      exit(1);
+    }
+  }
+  getrusage(RUSAGE_SELF, &r1);
+  if(r1.oublock != r0.oublock){
+    printf("%s: small writes went to the disk\n", s);
+    exit(1);
+  }
+
+  fd2 = open("wcfile", O_RDONLY);
+  n = read(fd2, buf, sizeof(buf));
+  if(n != 16 || memcmp(buf, "abcdefghijklmnop", 16) != 0){
+    printf("%s: read %d bytes before close\n", s, n);
+    exit(1);
+  }
+
+  getrusage(RUSAGE_SELF, &r0);
+  close(fd1);
+  getrusage(RUSAGE_SELF, &r1);
+  if(r1.oublock - r0.oublock != one){
+    printf("%s: close wrote %d blocks, not %d\n", s,
+           (int)(r1.oublock - r0.oublock), (int)one);
+    exit(1);
+  }
+  close(fd2);
+
+  fd2 = open("wcfile", O_RDONLY);
+  n = read(fd2, buf, sizeof(buf));
+  if(n != 16 || memcmp(buf, "abcdefghijklmnop", 16) != 0){
+    printf("%s: read %d bytes after close\n", s, n);
+    exit(1);
+  }
+  close(fd2);
+  unlink("wcfile");
+}
   
 
 // does chdir() call iput(p->cwd) in a transaction?
@@ -426,8 +515,8 @@ iputtest(char *s)
     printf("%s: chdir iputdir failed\n", s);
     exit(1);
   }
-  if(unlink("../iputdir") < 0){
-    printf("%s: unlink ../iputdir failed\n", s);
+  if(rmdir("../iputdir") < 0){
+    printf("%s: rmdir ../iputdir failed\n", s);
     exit(1);
   }
   if(chdir("/") < 0){
@@ -456,8 +545,8 @@ exitiputtest(char *s)
       printf("%s: child chdir failed\n", s);
       exit(1);
     }
-    if(unlink("../iputdir") < 0){
-      printf("%s: unlink ../iputdir failed\n", s);
+    if(rmdir("../iputdir") < 0){
+      printf("%s: rmdir ../iputdir failed\n", s);
       exit(1);
     }
     exit(0);
@@ -500,8 +589,8 @@ openiputtest(char *s)
     exit(0);
   }
   sleep(1);
-  if(unlink("oidir") != 0){
-    printf("%s: unlink failed\n", s);
+  if(rmdir("oidir") != 0){
+    printf("%s: rmdir failed\n", s);
     exit(1);
   }
   wait(&xstatus);
@@ -663,8 +752,8 @@ void dirtest(char *s)
     exit(1);
   }
 
-  if(unlink("dir0") < 0){
-    printf("%s: unlink dir0 failed\n", s);
+  if(rmdir("dir0") < 0){
+    printf("%s: rmdir dir0 failed\n", s);
     exit(1);
   }
 }
@@ -780,6 +869,49 @@ pipe1(char *s)
 }
 
 
+// a reader blocked on an empty pipe sees end-of-file when the last
+// write end closes, and writing to a pipe without readers fails.
+void
+pipehangup(char *s)
+{
+  int fds[2], pid, xstatus;
+  char c;
+
+  if(pipe(fds) != 0){
+    printf("%s: pipe() failed\n", s);
+    exit(1);
+  }
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork() failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    close(fds[1]);
+    exit(read(fds[0], &c, 1) == 0 ? 0 : 1);
+  }
+  close(fds[0]);
+  sleep(2);
+  close(fds[1]);
+  wait(&xstatus);
+  if(xstatus != 0){
+    printf("%s: blocked reader did not see end-of-file\n", s);
+    exit(1);
+  }
+
+  if(pipe(fds) != 0){
+    printf("%s: pipe() failed\n", s);
+    exit(1);
+  }
+  close(fds[0]);
+  if(write(fds[1], "x", 1) != -1){
+    printf("%s: write to a pipe without readers succeeded\n", s);
+    exit(1);
+  }
+  close(fds[1]);
+}
+
+
 // test if child is killed (status = -1)
 void
 killstatus(char *s)
@@ -1382,6 +1514,55 @@ linktest(char *s)
   }
 }
 
+// a failed link leaves the link count and the directory unchanged.
+void
+linkfail(char *s)
+{
+  struct stat st;
+  int fd;
+
+  unlink("lff");
+  unlink("lfexists");
+  fd = open("lff", O_CREATE|O_RDWR);
+  if(fd < 0){
+    printf("%s: create lff failed\n", s);
+    exit(1);
+  }
+  close(fd);
+  fd = open("lfexists", O_CREATE|O_RDWR);
+  if(fd < 0){
+    printf("%s: create lfexists failed\n", s);
+    exit(1);
+  }
+  close(fd);
+
+  if(link("lff", "lfexists") == 0 || link("lff", "lfnodir/x") == 0 ||
+     link("lff", "lff/x") == 0 || link(".", "lfdot") == 0){
+    printf("%s: bad link succeeded\n", s);
+    exit(1);
+  }
+  if(stat("lff", &st) < 0 || st.nlink != 1){
+    printf("%s: failed link changed nlink\n", s);
+    exit(1);
+  }
+  if(stat("lfdot", &st) == 0){
+    printf("%s: failed link created lfdot\n", s);
+    exit(1);
+  }
+
+  if(link("lff", "lfnew") != 0){
+    printf("%s: link failed\n", s);
+    exit(1);
+  }
+  if(stat("lff", &st) < 0 || st.nlink != 2){
+    printf("%s: link did not bump nlink\n", s);
+    exit(1);
+  }
+  unlink("lfnew");
+  unlink("lfexists");
+  unlink("lff");
+}
+
 // test concurrent create/link/unlink of the same file
 void
 concreate(char *s)
@@ -1572,8 +1753,8 @@ subdir(char *s)
   write(fd, "ff", 2);
   close(fd);
 
-  if(unlink("dd") >= 0){
-    printf("%s: unlink dd (non-empty dir) succeeded!\n", s);
+  if(rmdir("dd") >= 0){
+    printf("%s: rmdir dd (non-empty dir) succeeded!\n", s);
     exit(1);
   }
 
@@ -1582,6 +1763,11 @@ subdir(char *s)
     exit(1);
   }
 
+  if(link("dd/dd", "dd/dlink") == 0){
+    printf("%s: link dd/dd (dir) succeeded!\n", s);
+    exit(1);
+  }
+
   fd = open("dd/dd/ff", O_CREATE | O_RDWR);
   if(fd < 0){
     printf("%s: create dd/dd/ff failed\n", s);
@@ -1718,16 +1904,110 @@ subdir(char *s)
     printf("%s: unlink dd/ff failed\n", s);
     exit(1);
   }
-  if(unlink("dd") == 0){
-    printf("%s: unlink non-empty dd succeeded!\n", s);
+  if(rmdir("dd") == 0){
+    printf("%s: rmdir non-empty dd succeeded!\n", s);
+    exit(1);
+  }
+  if(rmdir("dd/dd") < 0){
+    printf("%s: rmdir dd/dd failed\n", s);
+    exit(1);
+  }
+  if(rmdir("dd") < 0){
+    printf("%s: rmdir dd failed\n", s);
+    exit(1);
+  }
+}
+
+// rmdir removes only empty directories, and unlink
+// removes only non-directories.
+void
+rmdirtest(char *s)
+{
+  int fd;
+
+  if(mkdir("rmd") != 0){
+    printf("%s: mkdir rmd failed\n", s);
+    exit(1);
+  }
+  fd = open("rmd/f", O_CREATE|O_RDWR);
+  if(fd < 0){
+    printf("%s: create rmd/f failed\n", s);
+    exit(1);
+  }
+  close(fd);
+
+  if(rmdir("rmd/f") == 0){
+    printf("%s: rmdir of a file succeeded!\n", s);
+    exit(1);
+  }
+  if(rmdir("rmd") == 0){
+    printf("%s: rmdir of a non-empty dir succeeded!\n", s);
+    exit(1);
+  }
+  if(unlink("rmd/f") != 0){
+    printf("%s: unlink rmd/f failed\n", s);
+    exit(1);
+  }
+  if(unlink("rmd") == 0){
+    printf("%s: unlink of a dir succeeded!\n", s);
+    exit(1);
+  }
+  if(rmdir("rmd") != 0){
+    printf("%s: rmdir of an empty dir failed\n", s);
+    exit(1);
+  }
+  if(open("rmd", O_RDONLY) >= 0){
+    printf("%s: rmd still exists\n", s);
+    exit(1);
+  }
+}
+
+// directory lookups skip the empty slots left by unlink,
+// and a directory holding only empty slots is empty.
+void
+dirholes(char *s)
+{
+  char name[16];
+  int fd, i;
+
+  if(mkdir("holed") != 0){
+    printf("%s: mkdir holed failed\n", s);
+    exit(1);
+  }
+  for(i = 0; i < 3; i++){
+    strcpy(name, "holed/a");
+    name[6] += i;
+    fd = open(name, O_CREATE|O_RDWR);
+    if(fd < 0){
+      printf("%s: create %s failed\n", s, name);
+      exit(1);
+    }
+    close(fd);
+  }
+  if(unlink("holed/a") != 0 || unlink("holed/b") != 0){
+    printf("%s: unlink failed\n", s);
+    exit(1);
+  }
+  fd = open("holed/c", O_RDONLY);
+  if(fd < 0){
+    printf("%s: open past empty slots failed\n", s);
+    exit(1);
+  }
+  close(fd);
+  if(open("holed/a", O_RDONLY) >= 0){
+    printf("%s: unlinked holed/a still exists\n", s);
+    exit(1);
+  }
+  if(rmdir("holed") == 0){
+    printf("%s: rmdir of a non-empty dir succeeded!\n", s);
     exit(1);
   }
-  if(unlink("dd/dd") < 0){
-    printf("%s: unlink dd/dd failed\n", s);
+  if(unlink("holed/c") != 0){
+    printf("%s: unlink holed/c failed\n", s);
     exit(1);
   }
-  if(unlink("dd") < 0){
-    printf("%s: unlink dd failed\n", s);
+  if(rmdir("holed") != 0){
+    printf("%s: rmdir of a dir with only empty slots failed\n", s);
     exit(1);
   }
 }
@@ -1758,6 +2038,77 @@ bigwrite(char *s)
   }
 }
 
+// concurrent reads and writes of files larger than the buffer cache,
+// so that many disk requests complete while others are being issued.
+// a missed completion wakeup in the virtio disk driver hangs a child.
+void
+diskrw(char *s)
+{
+  int nchildren = 4;
+  int nblocks = 40;
+  int iters = 3;
+
+  for(int ci = 0; ci < nchildren; ci++){
+    int pid = fork();
+    if(pid < 0){
+      printf("%s: fork failed\n", s);
+      exit(1);
+    }
+
+    if(pid == 0){
+      char name[3];
+      char data[BSIZE];
+      name[0] = 'd';
+      name[1] = 'a' + ci;
+      name[2] = '\0';
+
+      for(int it = 0; it < iters; it++){
+        int fd = open(name, O_CREATE | O_RDWR);
+        if(fd < 0){
+          printf("%s: cannot create %s\n", s, name);
+          exit(1);
+        }
+        for(int b = 0; b < nblocks; b++){
+          memset(data, ci + b + it, sizeof(data));
+          if(write(fd, data, sizeof(data)) != sizeof(data)){
+            printf("%s: write %s failed\n", s, name);
+            exit(1);
+          }
+        }
+        close(fd);
+
+        fd = open(name, O_RDONLY);
+        if(fd < 0){
+          printf("%s: cannot open %s\n", s, name);
+          exit(1);
+        }
+        for(int b = 0; b < nblocks; b++){
+          if(read(fd, data, sizeof(data)) != sizeof(data)){
+            printf("%s: read %s failed\n", s, name);
+            exit(1);
+          }
+          for(int i = 0; i < sizeof(data); i++){
+            if(data[i] != (char)(ci + b + it)){
+              printf("%s: %s has wrong content\n", s, name);
+              exit(1);
+            }
+          }
+        }
+        close(fd);
+        unlink(name);
+      }
+      exit(0);
+    }
+  }
+
+  for(int ci = 0; ci < nchildren; ci++){
+    int st = 0;
+    wait(&st);
+    if(st != 0)
+      exit(st);
+  }
+}
+
 // concurrent writes to try to provoke deadlock in the virtio disk
 // driver.
 void
@@ -1870,45 +2221,46 @@ fourteen(char *s)
 {
   int fd;
 
-  // DIRSIZ is 14.
+  // DIRSIZ is 14. Longer names are refused rather than truncated,
+  // so they cannot collide with a 14-character name.
 
   if(mkdir("12345678901234") != 0){
     printf("%s: mkdir 12345678901234 failed\n", s);
     exit(1);
   }
-  if(mkdir("12345678901234/123456789012345") != 0){
-    printf("%s: mkdir 12345678901234/123456789012345 failed\n", s);
+  if(mkdir("12345678901234/123456789012345") == 0){
+    printf("%s: mkdir 12345678901234/123456789012345 succeeded!\n", s);
+    exit(1);
+  }
+  fd = open("123456789012345/12345678901234", O_CREATE);
+  if(fd >= 0){
+    printf("%s: create 123456789012345/12345678901234 succeeded!\n", s);
     exit(1);
   }
-  fd = open("123456789012345/123456789012345/123456789012345", O_CREATE);
+  fd = open("12345678901234/12345678901234", O_CREATE);
   if(fd < 0){
-    printf("%s: create 123456789012345/123456789012345/123456789012345 failed\n", s);
+    printf("%s: create 12345678901234/12345678901234 failed\n", s);
     exit(1);
   }
   close(fd);
-  fd = open("12345678901234/12345678901234/12345678901234", 0);
-  if(fd < 0){
-    printf("%s: open 12345678901234/12345678901234/12345678901234 failed\n", s);
+  fd = open("12345678901234/123456789012345", 0);
+  if(fd >= 0){
+    printf("%s: open 12345678901234/123456789012345 succeeded!\n", s);
+    exit(1);
+  }
+  if(link("12345678901234/12345678901234", "12345678901234/123456789012345") == 0){
+    printf("%s: link to 12345678901234/123456789012345 succeeded!\n", s);
     exit(1);
   }
-  close(fd);
 
   if(mkdir("12345678901234/12345678901234") == 0){
     printf("%s: mkdir 12345678901234/12345678901234 succeeded!\n", s);
     exit(1);
   }
-  if(mkdir("123456789012345/12345678901234") == 0){
-    printf("%s: mkdir 12345678901234/123456789012345 succeeded!\n", s);
-    exit(1);
-  }
 
   // clean up
-  unlink("123456789012345/12345678901234");
   unlink("12345678901234/12345678901234");
-  unlink("12345678901234/12345678901234/12345678901234");
-  unlink("123456789012345/123456789012345/123456789012345");
-  unlink("12345678901234/123456789012345");
-  unlink("12345678901234");
+  rmdir("12345678901234");
 }
 
 void
@@ -1942,8 +2294,12 @@ rmdot(char *s)
     printf("%s: unlink dots/.. worked!\n", s);
     exit(1);
   }
-  if(unlink("dots") != 0){
-    printf("%s: unlink dots failed!\n", s);
+  if(rmdir("dots/.") == 0){
+    printf("%s: rmdir dots/. worked!\n", s);
+    exit(1);
+  }
+  if(rmdir("dots") != 0){
+    printf("%s: rmdir dots failed!\n", s);
     exit(1);
   }
 }
@@ -2034,7 +2390,7 @@ iref(char *s)
   // clean up
   for(i = 0; i < NINODE + 1; i++){
     chdir("..");
-    unlink("irefd");
+    rmdir("irefd");
   }
 
   chdir("/");
@@ -2080,48 +2436,982 @@ forktest(char *s)
   }
 }
 
+// a CPU-bound child accumulates user ticks, and a child
+// that writes a file issues block writes; both show up in
+// the parent's RUSAGE_CHILDREN once it has waited.
 void
-sbrkbasic(char *s)
+rusagetest(char *s)
 {
-  enum { TOOMUCH=1024*1024*1024};
-  int i, pid, xstatus;
-  char *c, *a, *b;
+  struct rusage ru;
+  int i, pid, fd, xstatus;
+
+  if(getrusage(RUSAGE_SELF, &ru) < 0){
+    printf("%s: getrusage failed\n", s);
+    exit(1);
+  }
 
-  // does sbrk() return the expected failure value?
   pid = fork();
   if(pid < 0){
-    printf("fork failed in sbrkbasic\n");
+    printf("%s: fork failed\n", s);
     exit(1);
   }
   if(pid == 0){
-    a = sbrk(TOOMUCH);
-    if(a == (char*)0xffffffffffffffffL){
-      // it's OK if this fails.
-      exit(0);
+    int t0 = uptime();
+    while(uptime() - t0 < 5)
+      ;
+    // write more blocks than the buffer cache holds, so that
+    // reading the file back has to go to the disk.
+    fd = open("rusagefile", O_CREATE|O_WRONLY);
+    for(i = 0; i < 40; i++){
+      if(write(fd, buf, BSIZE) != BSIZE){
+        printf("%s: write rusagefile failed\n", s);
+        exit(1);
+      }
     }
-    
-    for(b = a; b < a+TOOMUCH; b += 4096){
-      *b = 99;
+    close(fd);
+    fd = open("rusagefile", O_RDONLY);
+    for(i = 0; i < 40; i++){
+      if(read(fd, buf, BSIZE) != BSIZE){
+        printf("%s: read rusagefile failed\n", s);
+        exit(1);
+      }
     }
-    
-    // we should not get here! either sbrk(TOOMUCH)
-    // should have failed, or (with lazy allocation)
-    // a pagefault should have killed this process.
-    exit(1);
+    close(fd);
+    unlink("rusagefile");
+    exit(0);
   }
-
   wait(&xstatus);
-  if(xstatus == 1){
-    printf("%s: too much memory allocated!\n", s);
+  if(xstatus != 0)
+    exit(xstatus);
+
+  if(getrusage(RUSAGE_CHILDREN, &ru) < 0){
+    printf("%s: getrusage failed\n", s);
     exit(1);
   }
-
-  // can one sbrk() less than a page?
-  a = sbrk(0);
-  for(i = 0; i < 5000; i++){
-    b = sbrk(1);
-    if(b != a){
-      printf("%s: sbrk test failed %d %x %x\n", i, a, b);
+  if(ru.utime + ru.stime == 0){
+    printf("%s: no ticks charged to child\n", s);
+    exit(1);
+  }
+  if(ru.oublock == 0){
+    printf("%s: no block writes charged to child\n", s);
+    exit(1);
+  }
+  if(ru.inblock == 0){
+    printf("%s: no block reads charged to child\n", s);
+    exit(1);
+  }
+  if(getrusage(2, &ru) != -1){
+    printf("%s: getrusage accepted a bad who\n", s);
+    exit(1);
+  }
+}
+
+// lowered limits on open files and file size are enforced,
+// and the hard limit cannot be raised again.
+void
+rlimittest(char *s)
+{
+  struct rlimit rl;
+  int fd, fd2;
+
+  rl.cur = 4;
+  rl.max = 4;
+  if(setrlimit(RLIMIT_NOFILE, &rl) < 0){
+    printf("%s: setrlimit failed\n", s);
+    exit(1);
+  }
+  fd = open("echo", O_RDONLY);
+  if(fd != 3){
+    printf("%s: open got fd %d, wanted 3\n", s, fd);
+    exit(1);
+  }
+  fd2 = open("echo", O_RDONLY);
+  if(fd2 >= 0){
+    printf("%s: open beyond RLIMIT_NOFILE succeeded\n", s);
+    exit(1);
+  }
+  close(fd);
+  rl.max = 8;
+  if(setrlimit(RLIMIT_NOFILE, &rl) != -1){
+    printf("%s: raised the hard limit\n", s);
+    exit(1);
+  }
+
+  rl.cur = 10;
+  rl.max = RLIM_INFINITY;
+  if(setrlimit(RLIMIT_FSIZE, &rl) < 0){
+    printf("%s: setrlimit failed\n", s);
+    exit(1);
+  }
+  unlink("rlimitfile");
+  fd = open("rlimitfile", O_CREATE|O_WRONLY);
+  if(write(fd, "01234567", 8) != 8){
+    printf("%s: write within RLIMIT_FSIZE failed\n", s);
+    exit(1);
+  }
+  if(write(fd, "89ab", 4) != 2){
+    printf("%s: write across RLIMIT_FSIZE was not cut short\n", s);
+    exit(1);
+  }
+  if(write(fd, "x", 1) != -1){
+    printf("%s: write beyond RLIMIT_FSIZE succeeded\n", s);
+    exit(1);
+  }
+  if(getrlimit(RLIMIT_FSIZE, &rl) < 0 || rl.cur != 10){
+    printf("%s: getrlimit failed\n", s);
+    exit(1);
+  }
+  close(fd);
+  unlink("rlimitfile");
+}
+
+// prlimit returns the old limit while setting a new one,
+// and only targets the calling process.
+void
+prlimittest(char *s)
+{
+  struct rlimit old, new, cur;
+
+  new.cur = 5;
+  new.max = 6;
+  if(prlimit(0, RLIMIT_NOFILE, &new, &old) < 0){
+    printf("%s: prlimit failed\n", s);
+    exit(1);
+  }
+  if(old.cur != RLIM_INFINITY || old.max != RLIM_INFINITY){
+    printf("%s: prlimit returned the wrong old limit\n", s);
+    exit(1);
+  }
+  if(getrlimit(RLIMIT_NOFILE, &cur) < 0 || cur.cur != 5 || cur.max != 6){
+    printf("%s: prlimit did not set the limit\n", s);
+    exit(1);
+  }
+  if(prlimit(getpid(), RLIMIT_NOFILE, 0, &old) < 0 || old.cur != 5 || old.max != 6){
+    printf("%s: prlimit of own pid failed\n", s);
+    exit(1);
+  }
+
+  // a failed set changes nothing.
+  new.cur = 4;
+  new.max = 7;
+  if(prlimit(0, RLIMIT_NOFILE, &new, &old) != -1){
+    printf("%s: prlimit raised the hard limit\n", s);
+    exit(1);
+  }
+  if(getrlimit(RLIMIT_NOFILE, &cur) < 0 || cur.cur != 5 || cur.max != 6){
+    printf("%s: failed prlimit changed the limit\n", s);
+    exit(1);
+  }
+
+  if(prlimit(1, RLIMIT_NOFILE, 0, &old) != -1){
+    printf("%s: prlimit of another process succeeded\n", s);
+    exit(1);
+  }
+}
+
+// a process pinned to one CPU only runs there, across sleeps
+// and forks, and can be moved to another CPU.
+void
+affinitytest(char *s)
+{
+  struct rusage r0, r1;
+  int all, first, last, i, pid, xstatus;
+
+  all = sched_getaffinity(0);
+  if(all <= 0 || sched_getaffinity(getpid()) != all){
+    printf("%s: sched_getaffinity failed\n", s);
+    exit(1);
+  }
+  if(sched_setaffinity(0, 0) != -1 || sched_setaffinity(999999, all) != -1){
+    printf("%s: bad sched_setaffinity succeeded\n", s);
+    exit(1);
+  }
+
+  // find the lowest and highest running CPUs:
+  // sched_setaffinity only accepts masks with a running CPU.
+  first = last = -1;
+  for(i = 0; i < 8; i++){
+    if(sched_setaffinity(0, 1 << i) == 0){
+      if(getcpu() != i){
+        printf("%s: did not move to cpu %d\n", s, i);
+        exit(1);
+      }
+      if(first < 0)
+        first = i;
+      last = i;
+    }
+  }
+  if(first < 0){
+    printf("%s: no running cpu accepted\n", s);
+    exit(1);
+  }
+
+  if(sched_setaffinity(0, 1 << first) != 0 || sched_getaffinity(0) != 1 << first){
+    printf("%s: sched_setaffinity failed\n", s);
+    exit(1);
+  }
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  for(i = 0; i < 50; i++){
+    if(getcpu() != first){
+      printf("%s: pinned process ran on cpu %d\n", s, getcpu());
+      exit(1);
+    }
+    if(i % 10 == 0)
+      sleep(1);
+  }
+  if(pid == 0)
+    exit(0);
+  wait(&xstatus);
+  if(xstatus != 0)
+    exit(1);
+
+  if(sched_setaffinity(0, 1 << last) != 0 || getcpu() != last){
+    printf("%s: did not move to cpu %d\n", s, last);
+    exit(1);
+  }
+
+  // moving off our own CPU is a voluntary context switch.
+  if(first != last){
+    if(sched_setaffinity(0, 1 << first) != 0){
+      printf("%s: sched_setaffinity failed\n", s);
+      exit(1);
+    }
+    getrusage(RUSAGE_SELF, &r0);
+    if(sched_setaffinity(0, 1 << last) != 0){
+      printf("%s: sched_setaffinity failed\n", s);
+      exit(1);
+    }
+    getrusage(RUSAGE_SELF, &r1);
+    if(r1.nvcsw == r0.nvcsw){
+      printf("%s: move to cpu %d not counted as voluntary\n", s, last);
+      exit(1);
+    }
+  }
+}
+
+// statfs reports sane totals, and the free counts drop
+// while a file exists and come back once it is unlinked.
+void
+statfstest(char *s)
+{
+  struct statfs before, during, after;
+  char buf[BSIZE];
+  int fd;
+
+  unlink("statfsfile");
+  if(statfs(".", &before) < 0){
+    printf("%s: statfs failed\n", s);
+    exit(1);
+  }
+  if(before.bsize != BSIZE || before.bfree > before.blocks ||
+     before.ffree >= before.files){
+    printf("%s: bad totals\n", s);
+    exit(1);
+  }
+
+  fd = open("statfsfile", O_CREATE|O_WRONLY);
+  memset(buf, 'a', sizeof(buf));
+  write(fd, buf, sizeof(buf));
+  close(fd);
+  if(statfs("statfsfile", &during) < 0){
+    printf("%s: statfs failed\n", s);
+    exit(1);
+  }
+  if(during.bfree >= before.bfree || during.ffree != before.ffree - 1){
+    printf("%s: free counts did not drop\n", s);
+    exit(1);
+  }
+
+  unlink("statfsfile");
+  if(statfs("/", &after) < 0 || after.bfree != before.bfree ||
+     after.ffree != before.ffree){
+    printf("%s: free counts not restored\n", s);
+    exit(1);
+  }
+  if(statfs("nonexistent", &after) != -1){
+    printf("%s: statfs of a missing path succeeded\n", s);
+    exit(1);
+  }
+}
+
+// the file system stays consistent across creating, linking,
+// and removing files and directories, including an open
+// file that has been unlinked.
+void
+fscktest(char *s)
+{
+  int fd;
+
+  if(fsck() != 0){
+    printf("%s: file system inconsistent before the test\n", s);
+    exit(1);
+  }
+  if(mkdir("fsckdir") != 0 || mkdir("fsckdir/sub") != 0){
+    printf("%s: mkdir failed\n", s);
+    exit(1);
+  }
+  fd = open("fsckdir/f", O_CREATE|O_RDWR);
+  if(fd < 0 || write(fd, "data", 4) != 4 ||
+     link("fsckdir/f", "fsckdir/sub/g") != 0){
+    printf("%s: create failed\n", s);
+    exit(1);
+  }
+  if(fsck() != 0){
+    printf("%s: inconsistent after create\n", s);
+    exit(1);
+  }
+  if(unlink("fsckdir/sub/g") != 0 || unlink("fsckdir/f") != 0 ||
+     rmdir("fsckdir/sub") != 0){
+    printf("%s: unlink failed\n", s);
+    exit(1);
+  }
+  if(fsck() != 0){
+    printf("%s: inconsistent with an unlinked open file\n", s);
+    exit(1);
+  }
+  close(fd);
+  if(rmdir("fsckdir") != 0 || fsck() != 0){
+    printf("%s: inconsistent after unlink\n", s);
+    exit(1);
+  }
+}
+
+// stat of a device file reports its major/minor in st_rdev,
+// and regular files have no st_rdev.
+void
+rdevtest(char *s)
+{
+  struct stat st;
+
+  unlink("rdevfile");
+  if(mknod("rdevfile", 2, 7) < 0){
+    printf("%s: mknod failed\n", s);
+    exit(1);
+  }
+  if(stat("rdevfile", &st) < 0){
+    printf("%s: stat failed\n", s);
+    exit(1);
+  }
+  if(st.type != T_DEVICE || MAJOR(st.rdev) != 2 || MINOR(st.rdev) != 7){
+    printf("%s: wrong rdev %x\n", s, st.rdev);
+    exit(1);
+  }
+  unlink("rdevfile");
+
+  if(stat("README", &st) < 0){
+    printf("%s: stat README failed\n", s);
+    exit(1);
+  }
+  if(st.rdev != 0){
+    printf("%s: regular file has rdev %x\n", s, st.rdev);
+    exit(1);
+  }
+}
+
+// a FIFO passes data between processes that open it by name,
+// and opening it for reading waits for a writer.
+void
+fifotest(char *s)
+{
+  char buf[8];
+  int fd, pid, xstatus;
+
+  unlink("fifo0");
+  unlink("fifoopened");
+  if(mkfifo("fifo0") < 0){
+    printf("%s: mkfifo failed\n", s);
+    exit(1);
+  }
+  if(mkfifo("fifo0") == 0){
+    printf("%s: mkfifo of an existing name succeeded\n", s);
+    exit(1);
+  }
+
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    fd = open("fifo0", O_WRONLY);
+    if(fd < 0)
+      exit(1);
+    if(write(fd, "hello", 5) != 5)
+      exit(1);
+    close(fd);
+    exit(0);
+  }
+  fd = open("fifo0", O_RDONLY);
+  if(fd < 0){
+    printf("%s: open fifo for reading failed\n", s);
+    exit(1);
+  }
+  int n = 0, cc;
+  while((cc = read(fd, buf + n, sizeof(buf) - n)) > 0)
+    n += cc;
+  close(fd);
+  wait(&xstatus);
+  if(xstatus != 0 || n != 5 || memcmp(buf, "hello", 5) != 0){
+    printf("%s: data did not pass through the fifo\n", s);
+    exit(1);
+  }
+
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    fd = open("fifo0", O_RDONLY);
+    if(fd < 0)
+      exit(1);
+    close(open("fifoopened", O_CREATE|O_WRONLY));
+    close(fd);
+    exit(0);
+  }
+  sleep(5);
+  fd = open("fifoopened", O_RDONLY);
+  if(fd >= 0){
+    printf("%s: open for reading did not wait for a writer\n", s);
+    exit(1);
+  }
+  fd = open("fifo0", O_WRONLY);
+  if(fd < 0){
+    printf("%s: open fifo for writing failed\n", s);
+    exit(1);
+  }
+  close(fd);
+  wait(&xstatus);
+  if(xstatus != 0 || (fd = open("fifoopened", O_RDONLY)) < 0){
+    printf("%s: reader did not open the fifo\n", s);
+    exit(1);
+  }
+  close(fd);
+  unlink("fifoopened");
+  unlink("fifo0");
+}
+
+// with O_NONBLOCK, opening a FIFO for reading doesn't wait for a writer,
+// and opening it for writing fails if there is no reader.
+void
+fifononblock(char *s)
+{
+  char c;
+  int rfd, wfd;
+
+  unlink("fifo1");
+  if(mkfifo("fifo1") < 0){
+    printf("%s: mkfifo failed\n", s);
+    exit(1);
+  }
+  if(open("fifo1", O_WRONLY|O_NONBLOCK) >= 0){
+    printf("%s: open for writing without a reader succeeded\n", s);
+    exit(1);
+  }
+  rfd = open("fifo1", O_RDONLY|O_NONBLOCK);
+  if(rfd < 0){
+    printf("%s: open for reading without a writer failed\n", s);
+    exit(1);
+  }
+  if(read(rfd, &c, 1) != 0){
+    printf("%s: read without a writer did not return 0\n", s);
+    exit(1);
+  }
+  wfd = open("fifo1", O_WRONLY|O_NONBLOCK);
+  if(wfd < 0){
+    printf("%s: open for writing with a reader failed\n", s);
+    exit(1);
+  }
+  if(write(wfd, "x", 1) != 1 || read(rfd, &c, 1) != 1 || c != 'x'){
+    printf("%s: data did not pass through the fifo\n", s);
+    exit(1);
+  }
+  close(wfd);
+  close(rfd);
+  unlink("fifo1");
+}
+
+// a process can have more than NOFILE files open,
+// and a forked child inherits the descriptors beyond NOFILE.
+void
+manyfds(char *s)
+{
+  enum { N = NOFILE + 8 };
+  int fds[N];
+  char buf[2];
+  int pid, xstatus;
+
+  unlink("manyfds");
+  for(int i = 0; i < N; i++){
+    fds[i] = open("manyfds", O_CREATE|O_RDWR);
+    if(fds[i] < 0){
+      printf("%s: open %d failed\n", s, i);
+      exit(1);
+    }
+  }
+  if(fds[N-1] < NOFILE){
+    printf("%s: got no descriptor beyond NOFILE\n", s);
+    exit(1);
+  }
+  if(write(fds[N-1], "ab", 2) != 2){
+    printf("%s: write to fd %d failed\n", s, fds[N-1]);
+    exit(1);
+  }
+
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    if(read(fds[N-2], buf, 2) != 2 || buf[0] != 'a' || buf[1] != 'b')
+      exit(1);
+    exit(0);
+  }
+  wait(&xstatus);
+  if(xstatus != 0){
+    printf("%s: child could not read an inherited descriptor\n", s);
+    exit(1);
+  }
+
+  for(int i = 0; i < N; i++)
+    close(fds[i]);
+  if(read(fds[N-1], buf, 1) != -1){
+    printf("%s: read from a closed descriptor succeeded\n", s);
+    exit(1);
+  }
+  unlink("manyfds");
+}
+
+// close_range closes exactly the descriptors in its range,
+// including those beyond NOFILE.
+void
+closerange(char *s)
+{
+  enum { N = NOFILE + 4 };
+  int fds[N];
+  struct stat st;
+  int i, isopen;
+
+  for(i = 0; i < N; i++){
+    fds[i] = dup(0);
+    if(fds[i] < 0 || (i > 0 && fds[i] != fds[i-1] + 1)){
+      printf("%s: dup %d failed\n", s, i);
+      exit(1);
+    }
+  }
+  if(close_range(fds[1], fds[0], 0) != -1 || close_range(fds[0], fds[1], 1) != -1){
+    printf("%s: bad close_range succeeded\n", s);
+    exit(1);
+  }
+  if(close_range(fds[2], fds[N-3], 0) != 0){
+    printf("%s: close_range failed\n", s);
+    exit(1);
+  }
+  for(i = 0; i < N; i++){
+    isopen = fstat(fds[i], &st) == 0;
+    if(isopen != (i < 2 || i > N-3)){
+      printf("%s: fd %d is %s\n", s, fds[i], isopen ? "open" : "closed");
+      exit(1);
+    }
+  }
+  if(close_range(fds[0], ~0U, 0) != 0){
+    printf("%s: close_range to ~0U failed\n", s);
+    exit(1);
+  }
+  for(i = 0; i < N; i++){
+    if(fstat(fds[i], &st) == 0){
+      printf("%s: fd %d is still open\n", s, fds[i]);
+      exit(1);
+    }
+  }
+  if(fstat(0, &st) != 0){
+    printf("%s: fd 0 was closed\n", s);
+    exit(1);
+  }
+}
+
+// splice moves bytes from a file to a pipe and back, at the file
+// offset or at an explicit one, and one end must be a pipe.
+void
+splicetest(char *s)
+{
+  struct rlimit rl;
+  char buf[16];
+  int fd, fd2, fds[2];
+  uint64 off;
+
+  unlink("splicein");
+  unlink("spliceout");
+  fd = open("splicein", O_CREATE|O_RDWR);
+  if(fd < 0 || write(fd, "abcdefghij", 10) != 10){
+    printf("%s: write splicein failed\n", s);
+    exit(1);
+  }
+  close(fd);
+  fd = open("splicein", O_RDONLY);
+  fd2 = open("spliceout", O_CREATE|O_RDWR);
+  if(fd < 0 || fd2 < 0 || pipe(fds) != 0){
+    printf("%s: open failed\n", s);
+    exit(1);
+  }
+
+  if(splice(fd, 0, fds[1], 0, 4, 0) != 4){
+    printf("%s: splice from file offset failed\n", s);
+    exit(1);
+  }
+  if(read(fds[0], buf, sizeof(buf)) != 4 || memcmp(buf, "abcd", 4) != 0){
+    printf("%s: wrong bytes from file offset\n", s);
+    exit(1);
+  }
+
+  // stops at the end of the file, and leaves the file offset alone.
+  off = 7;
+  if(splice(fd, &off, fds[1], 0, sizeof(buf), 0) != 3 || off != 10){
+    printf("%s: splice from explicit offset failed\n", s);
+    exit(1);
+  }
+  if(read(fds[0], buf, sizeof(buf)) != 3 || memcmp(buf, "hij", 3) != 0){
+    printf("%s: wrong bytes from explicit offset\n", s);
+    exit(1);
+  }
+  if(read(fd, buf, 2) != 2 || memcmp(buf, "ef", 2) != 0){
+    printf("%s: file offset moved\n", s);
+    exit(1);
+  }
+
+  // stops when the pipe runs out of bytes.
+  if(write(fds[1], "xyz", 3) != 3 || splice(fds[0], 0, fd2, 0, sizeof(buf), 0) != 3){
+    printf("%s: splice to file failed\n", s);
+    exit(1);
+  }
+  close(fd2);
+  fd2 = open("spliceout", O_RDONLY);
+  if(fd2 < 0 || read(fd2, buf, sizeof(buf)) != 3 || memcmp(buf, "xyz", 3) != 0){
+    printf("%s: wrong bytes in spliceout\n", s);
+    exit(1);
+  }
+
+  off = 0;
+  if(splice(fd, 0, fd2, 0, 1, 0) != -1 ||
+     splice(fds[0], &off, fds[1], 0, 1, 0) != -1 ||
+     splice(fd, 0, fds[1], 0, 1, 1) != -1){
+    printf("%s: bad splice succeeded\n", s);
+    exit(1);
+  }
+
+  // stops at RLIMIT_FSIZE, and leaves the rest in the pipe.
+  close(fd2);
+  fd2 = open("spliceout", O_WRONLY);
+  rl.cur = 5;
+  rl.max = RLIM_INFINITY;
+  if(fd2 < 0 || write(fds[1], "1234", 4) != 4 || setrlimit(RLIMIT_FSIZE, &rl) < 0){
+    printf("%s: setup for RLIMIT_FSIZE failed\n", s);
+    exit(1);
+  }
+  off = 3;
+  if(splice(fds[0], 0, fd2, &off, 4, 0) != 2 || off != 5){
+    printf("%s: splice across RLIMIT_FSIZE was not cut short\n", s);
+    exit(1);
+  }
+  if(read(fds[0], buf, sizeof(buf)) != 2 || memcmp(buf, "34", 2) != 0){
+    printf("%s: splice took bytes it did not write\n", s);
+    exit(1);
+  }
+  rl.cur = RLIM_INFINITY;
+  setrlimit(RLIMIT_FSIZE, &rl);
+
+  close(fd);
+  close(fd2);
+  close(fds[0]);
+  close(fds[1]);
+  unlink("splicein");
+  unlink("spliceout");
+}
+
+// after chroot, absolute paths start at the new root,
+// and .. of the new root is the root itself.
+void
+chroottest(char *s)
+{
+  struct stat st1, st2;
+  int fd, pid, xstatus;
+
+  if(mkdir("chrootdir") < 0 || mkdir("chrootdir/sub") < 0){
+    printf("%s: mkdir failed\n", s);
+    exit(1);
+  }
+  fd = open("chrootdir/inside", O_CREATE|O_WRONLY);
+  if(fd < 0){
+    printf("%s: create failed\n", s);
+    exit(1);
+  }
+  close(fd);
+  if(chroot("chrootdir/inside") == 0){
+    printf("%s: chroot to a file succeeded\n", s);
+    exit(1);
+  }
+
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    if(chroot("chrootdir") < 0)
+      exit(1);
+    if((fd = open("/inside", O_RDONLY)) < 0)
+      exit(2);
+    close(fd);
+    if(open("/chrootdir", O_RDONLY) >= 0)
+      exit(3);
+    if((fd = open("/sub/../../../inside", O_RDONLY)) < 0)
+      exit(4);
+    close(fd);
+    if(chdir("/sub") < 0 || chdir("../..") < 0)
+      exit(5);
+    if(stat(".", &st1) < 0 || stat("/", &st2) < 0 || st1.ino != st2.ino)
+      exit(6);
+    exit(0);
+  }
+  wait(&xstatus);
+  if(xstatus != 0){
+    printf("%s: check %d failed in the chrooted child\n", s, xstatus);
+    exit(1);
+  }
+
+  unlink("chrootdir/inside");
+  rmdir("chrootdir/sub");
+  rmdir("chrootdir");
+}
+
+// the disk trace pairs each completed request with its submission,
+// in order of time.
+void
+disktracetest(char *s)
+{
+  struct disktrace tr[64];
+  uint64 pending[8];
+  int fd, tfd, i, n, m, npairs;
+
+  unlink("disktrace");
+  if(mknod("disktrace", DISKTRACE, 0) < 0){
+    printf("%s: mknod failed\n", s);
+    exit(1);
+  }
+  fd = open("tracefile", O_CREATE|O_RDWR);
+  if(fd < 0){
+    printf("%s: create failed\n", s);
+    exit(1);
+  }
+  close(fd);
+  tfd = open("disktrace", O_RDONLY);
+  if(tfd < 0){
+    printf("%s: open disktrace failed\n", s);
+    exit(1);
+  }
+  // drain old records; nothing is in flight between system calls.
+  while((n = read(tfd, tr, sizeof(tr))) > 0)
+    ;
+  if(n < 0){
+    printf("%s: read disktrace failed\n", s);
+    exit(1);
+  }
+
+  // a single transaction, well within the trace's 64 records.
+  fd = open("tracefile", O_WRONLY);
+  if(fd < 0){
+    printf("%s: open failed\n", s);
+    exit(1);
+  }
+  memset(buf, 'x', BSIZE);
+  if(write(fd, buf, BSIZE) != BSIZE){
+    printf("%s: write failed\n", s);
+    exit(1);
+  }
+  close(fd);
+
+  n = 0;
+  while(n < sizeof(tr) && (m = read(tfd, (char*)tr + n, sizeof(tr) - n)) > 0)
+    n += m;
+  if(n <= 0 || n % sizeof(tr[0]) != 0){
+    printf("%s: read disktrace returned %d\n", s, n);
+    exit(1);
+  }
+  n /= sizeof(tr[0]);
+  for(i = 0; i < 8; i++)
+    pending[i] = -1;
+  npairs = 0;
+  for(i = 0; i < n; i++){
+    if(i > 0 && tr[i].ticks < tr[i-1].ticks){
+      printf("%s: time went backwards\n", s);
+      exit(1);
+    }
+    if(tr[i].desc >= 8){
+      printf("%s: bad descriptor %d\n", s, tr[i].desc);
+      exit(1);
+    }
+    if(tr[i].event == TRACE_SUBMIT){
+      pending[tr[i].desc] = tr[i].sector;
+    } else if(tr[i].event == TRACE_COMPLETE && pending[tr[i].desc] == tr[i].sector){
+      pending[tr[i].desc] = -1;
+      npairs++;
+    } else {
+      printf("%s: unmatched completion of sector %d\n", s, (int)tr[i].sector);
+      exit(1);
+    }
+  }
+  if(npairs == 0 || npairs * 2 != n){
+    printf("%s: %d pairs in %d records\n", s, npairs, n);
+    exit(1);
+  }
+  close(tfd);
+  unlink("disktrace");
+  unlink("tracefile");
+}
+
+// the disk id device reads back the serial the Makefile gives
+// qemu's virtio-blk-device, NUL-padded to DISKID_BYTES.
+void
+diskidtest(char *s)
+{
+  char id[DISKID_BYTES + 8];
+  char *want = "rv6-fs.img";
+  int fd, n, i;
+
+  unlink("diskid");
+  if(mknod("diskid", DISKID, 0) < 0){
+    printf("%s: mknod failed\n", s);
+    exit(1);
+  }
+  fd = open("diskid", O_RDONLY);
+  if(fd < 0){
+    printf("%s: open diskid failed\n", s);
+    exit(1);
+  }
+  memset(id, 'x', sizeof(id));
+  n = read(fd, id, sizeof(id));
+  if(n != DISKID_BYTES){
+    printf("%s: read diskid returned %d\n", s, n);
+    exit(1);
+  }
+  if(memcmp(id, want, strlen(want)) != 0){
+    printf("%s: wrong disk id\n", s);
+    exit(1);
+  }
+  for(i = strlen(want); i < DISKID_BYTES; i++){
+    if(id[i] != 0){
+      printf("%s: disk id not padded with NULs\n", s);
+      exit(1);
+    }
+  }
+  // a short read gets the start of the id.
+  memset(id, 'x', sizeof(id));
+  if(read(fd, id, 3) != 3 || memcmp(id, want, 3) != 0 || id[3] != 'x'){
+    printf("%s: short read of diskid failed\n", s);
+    exit(1);
+  }
+  close(fd);
+  unlink("diskid");
+}
+
+// the blocks and inode of an unlinked file stay allocated
+// while it is open, and are freed when the last descriptor is closed.
+void
+unlinkfree(char *s)
+{
+  struct statfs before, during, after;
+  char data[BSIZE];
+  int fd, fd2;
+
+  unlink("unlinkfree");
+  if(statfs(".", &before) < 0){
+    printf("%s: statfs failed\n", s);
+    exit(1);
+  }
+  fd = open("unlinkfree", O_CREATE|O_RDWR);
+  if(fd < 0){
+    printf("%s: create failed\n", s);
+    exit(1);
+  }
+  memset(data, 'u', sizeof(data));
+  for(int i = 0; i < 4; i++){
+    if(write(fd, data, sizeof(data)) != sizeof(data)){
+      printf("%s: write failed\n", s);
+      exit(1);
+    }
+  }
+  fd2 = dup(fd);
+  if(unlink("unlinkfree") < 0){
+    printf("%s: unlink failed\n", s);
+    exit(1);
+  }
+
+  close(fd);
+  if(statfs(".", &during) < 0 || during.bfree >= before.bfree ||
+     during.ffree != before.ffree - 1){
+    printf("%s: unlinked file freed while open\n", s);
+    exit(1);
+  }
+  if(write(fd2, "end", 3) != 3){
+    printf("%s: write to unlinked file failed\n", s);
+    exit(1);
+  }
+
+  close(fd2);
+  if(statfs(".", &after) < 0 || after.bfree != before.bfree ||
+     after.ffree != before.ffree){
+    printf("%s: unlinked file not freed on close\n", s);
+    exit(1);
+  }
+}
+
+void
+sbrkbasic(char *s)
+{
+  enum { TOOMUCH=1024*1024*1024};
+  int i, pid, xstatus;
+  char *c, *a, *b;
+
+  // does sbrk() return the expected failure value?
+  pid = fork();
+  if(pid < 0){
+    printf("fork failed in sbrkbasic\n");
+    exit(1);
+  }
+  if(pid == 0){
+    a = sbrk(TOOMUCH);
+    if(a == (char*)0xffffffffffffffffL){
+      // it's OK if this fails.
+      exit(0);
+    }
+    
+    for(b = a; b < a+TOOMUCH; b += 4096){
+      *b = 99;
+    }
+    
+    // we should not get here! either sbrk(TOOMUCH)
+    // should have failed, or (with lazy allocation)
+    // a pagefault should have killed this process.
+    exit(1);
+  }
+
+  wait(&xstatus);
+  if(xstatus == 1){
+    printf("%s: too much memory allocated!\n", s);
+    exit(1);
+  }
+
+  // can one sbrk() less than a page?
+  a = sbrk(0);
+  for(i = 0; i < 5000; i++){
+    b = sbrk(1);
+    if(b != a){
+      printf("%s: sbrk test failed %d %x %x\n", i, a, b);
       exit(1);
     }
     *b = 1;
@@ -2204,6 +3494,117 @@ sbrkmuch(char *s)
   }
 }
 
+// dmesg returns the most recent kernel messages oldest first, such
+// as those usertrap() prints about faulting processes, and drops the
+// oldest once more than KMSGSIZE bytes have been printed.
+void
+dmesgtest(char *s)
+{
+  enum { N = 48 };
+  static char buf[KMSGSIZE], last[16];
+  int i, j, n, pid, pids[N], xstatus;
+
+  for(i = 0; i < N; i++){
+    pids[i] = fork();
+    if(pids[i] < 0){
+      printf("%s: fork failed\n", s);
+      exit(1);
+    }
+    if(pids[i] == 0){
+      printf("%s: oops could read %x\n", s, *(volatile char*)KERNBASE);
+      exit(1);
+    }
+    wait(&xstatus);
+    if(xstatus != -1){
+      printf("%s: child was not killed\n", s);
+      exit(1);
+    }
+  }
+
+  n = dmesg(buf, sizeof(buf));
+  if(n != KMSGSIZE){
+    printf("%s: dmesg returned %d\n", s, n);
+    exit(1);
+  }
+  if(dmesg(last, sizeof(last)) != sizeof(last) ||
+     memcmp(last, buf + n - sizeof(last), sizeof(last)) != 0){
+    printf("%s: dmesg with a small buffer is not the newest bytes\n", s);
+    exit(1);
+  }
+
+  // the log holds only messages about our children, in order,
+  // ending with the newest but no longer with the oldest.
+  j = -1;
+  for(i = 0; i + 4 <= n; i++){
+    if(memcmp(buf + i, "pid=", 4) != 0)
+      continue;
+    pid = atoi(buf + i + 4);
+    if(j < 0){
+      for(j = 0; j < N && pids[j] != pid; j++)
+        ;
+      if(j == 0 || j == N){
+        printf("%s: oldest message is about pid %d\n", s, pid);
+        exit(1);
+      }
+    } else if(++j == N || pids[j] != pid){
+      printf("%s: message about pid %d out of order\n", s, pid);
+      exit(1);
+    }
+  }
+  if(j != N-1){
+    printf("%s: newest message missing\n", s);
+    exit(1);
+  }
+}
+
+// sleep(n) returns after at least n ticks and not much later, also
+// when several timers share a bucket of the kernel's timer wheel
+// (deadlines 32 ticks apart) or have the same deadline.
+void
+sleeptimer(char *s)
+{
+  int i, n, t0, t1, xstatus;
+  int base, target, pid, d;
+  int delay[] = { 0, 0, 32, 64 };
+
+  for(n = 1; n <= 16; n *= 4){
+    t0 = uptime();
+    if(sleep(n) < 0){
+      printf("%s: sleep(%d) failed\n", s, n);
+      exit(1);
+    }
+    t1 = uptime();
+    if(t1 - t0 < n || t1 - t0 > n + 10){
+      printf("%s: sleep(%d) took %d ticks\n", s, n, t1 - t0);
+      exit(1);
+    }
+  }
+
+  base = uptime() + 10;
+  for(i = 0; i < sizeof(delay)/sizeof(delay[0]); i++){
+    pid = fork();
+    if(pid < 0){
+      printf("%s: fork failed\n", s);
+      exit(1);
+    }
+    if(pid == 0){
+      target = base + delay[i];
+      d = target - uptime();
+      if(d > 0)
+        sleep(d);
+      t1 = uptime();
+      exit(t1 < target || t1 > target + 10);
+    }
+  }
+  for(i = 0; i < sizeof(delay)/sizeof(delay[0]); i++){
+    wait(&xstatus);
+    if(xstatus != 0){
+      printf("%s: a timer in a shared bucket woke at the wrong tick\n", s);
+      exit(1);
+    }
+  }
+}
+
 // can we read the kernel's memory?
 void
 kernmem(char *s)
@@ -2228,6 +3629,74 @@ kernmem(char *s)
   }
 }
 
+// a page made read-only faults on a user write, refuses kernel
+// writes, and becomes writable again after mprotect restores it.
+void
+mprotecttest(char *s)
+{
+  char *cur, *page;
+  int fd, pid, xstatus;
+
+  cur = sbrk(0);
+  page = (char*)PGROUNDUP((uint64)cur);
+  if(sbrk(page + PGSIZE - cur) == (char*)-1){
+    printf("%s: sbrk failed\n", s);
+    exit(1);
+  }
+  page[0] = 'a';
+
+  if(mprotect(page, PGSIZE, PROT_WRITE) != -1 || mprotect(page + 1, 1, PROT_READ) != -1 ||
+     mprotect(page, PGSIZE + 1, PROT_READ) != -1){
+    printf("%s: bad mprotect succeeded\n", s);
+    exit(1);
+  }
+  if(mprotect(page, PGSIZE, PROT_READ) < 0){
+    printf("%s: mprotect read-only failed\n", s);
+    exit(1);
+  }
+  if(page[0] != 'a'){
+    printf("%s: read-only page lost its contents\n", s);
+    exit(1);
+  }
+
+  fd = open("README", O_RDONLY);
+  if(fd < 0){
+    printf("%s: open README failed\n", s);
+    exit(1);
+  }
+  if(read(fd, page, 1) != -1){
+    printf("%s: read into a read-only page succeeded\n", s);
+    exit(1);
+  }
+  close(fd);
+
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    page[0] = 'b';
+    printf("%s: oops could write a read-only page\n", s);
+    exit(1);
+  }
+  wait(&xstatus);
+  if(xstatus != -1){  // did kernel kill child?
+    printf("%s: write to read-only page was not killed\n", s);
+    exit(1);
+  }
+
+  if(mprotect(page, PGSIZE, PROT_READ|PROT_WRITE) < 0){
+    printf("%s: mprotect read-write failed\n", s);
+    exit(1);
+  }
+  page[0] = 'b';
+  if(page[0] != 'b'){
+    printf("%s: write after restoring permission was lost\n", s);
+    exit(1);
+  }
+}
+
 // if we run the system out of memory, does it clean up the last
 // failed allocation?
 void
@@ -2393,6 +3862,200 @@ bigargtest(char *s)
   close(fd);
 }
 
+// execve passes envp on the new program's stack,
+// and exec passes an empty environment.
+void
+envtest(char *s)
+{
+  char *args[] = { "env", 0 };
+  char *envp[] = { "RV6=yes", "PATH=/", 0 };
+  char buf[32];
+  int pid, fd, n, xstatus;
+
+  for(int withenv = 1; withenv >= 0; withenv--){
+    unlink("envfile");
+    pid = fork();
+    if(pid < 0){
+      printf("%s: fork failed\n", s);
+      exit(1);
+    }
+    if(pid == 0){
+      close(1);
+      if(open("envfile", O_CREATE|O_WRONLY) != 1){
+        printf("%s: open envfile failed\n", s);
+        exit(1);
+      }
+      if(withenv)
+        execve("env", args, envp);
+      else
+        exec("env", args);
+      printf("%s: exec env failed\n", s);
+      exit(1);
+    }
+    wait(&xstatus);
+    if(xstatus != 0)
+      exit(xstatus);
+
+    fd = open("envfile", O_RDONLY);
+    n = read(fd, buf, sizeof(buf) - 1);
+    close(fd);
+    unlink("envfile");
+    if(n < 0)
+      n = 0;
+    buf[n] = '\0';
+    if(withenv && strcmp(buf, "RV6=yes\nPATH=/\n") != 0){
+      printf("%s: env printed %s\n", s, buf);
+      exit(1);
+    }
+    if(!withenv && n != 0){
+      printf("%s: env printed %s\n", s, buf);
+      exit(1);
+    }
+  }
+}
+
+// exec must refuse arguments whose total size exceeds
+// ARG_MAX, and leave the calling process intact.
+void
+argmaxtest(char *s)
+{
+  static char big[ARG_MAX/2];
+  char *args[] = { big, big, big, 0 };
+  int ret;
+
+  memset(big, 'x', sizeof(big) - 1);
+  big[sizeof(big) - 1] = '\0';
+  ret = exec("echo", args);
+  if(ret != -1){
+    printf("%s: exec with too large arguments returned %d\n", s, ret);
+    exit(1);
+  }
+}
+
+// exec passes up to MAXARG small arguments, and refuses more.
+void
+manyargs(char *s)
+{
+  char *args[MAXARG + 2];
+  char strs[MAXARG + 1][2];
+  char out[2 * MAXARG];
+  int fd, pid, xstatus, n;
+
+  for(int i = 0; i <= MAXARG; i++){
+    strs[i][0] = 'a' + i % 26;
+    strs[i][1] = '\0';
+    args[i] = strs[i];
+  }
+  args[MAXARG + 1] = 0;
+  if(exec("echo", args) != -1){
+    printf("%s: exec with more than MAXARG arguments succeeded\n", s);
+    exit(1);
+  }
+
+  args[MAXARG] = 0;
+  unlink("manyargs.out");
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    close(1);
+    if(open("manyargs.out", O_CREATE|O_WRONLY) != 1)
+      exit(1);
+    exec("echo", args);
+    exit(1);
+  }
+  wait(&xstatus);
+  if(xstatus != 0){
+    printf("%s: exec with MAXARG arguments failed\n", s);
+    exit(1);
+  }
+
+  fd = open("manyargs.out", O_RDONLY);
+  if(fd < 0){
+    printf("%s: open manyargs.out failed\n", s);
+    exit(1);
+  }
+  n = read(fd, out, sizeof(out));
+  close(fd);
+  unlink("manyargs.out");
+  // echo prints args[1..MAXARG-1], each followed by a space or newline.
+  if(n != 2 * (MAXARG - 1) || out[0] != 'b' || out[n - 2] != strs[MAXARG - 1][0]){
+    printf("%s: echo printed the wrong arguments\n", s);
+    exit(1);
+  }
+}
+
+// run echo with args, and check that it prints "a b c d e".
+static void
+echoargs(char *s, char **args)
+{
+  char out[16];
+  int fd, pid, xstatus, n;
+
+  unlink("argvspan.out");
+  pid = fork();
+  if(pid < 0){
+    printf("%s: fork failed\n", s);
+    exit(1);
+  }
+  if(pid == 0){
+    close(1);
+    if(open("argvspan.out", O_CREATE|O_WRONLY) != 1)
+      exit(1);
+    exec("echo", args);
+    exit(1);
+  }
+  wait(&xstatus);
+  if(xstatus != 0){
+    printf("%s: exec failed\n", s);
+    exit(1);
+  }
+  fd = open("argvspan.out", O_RDONLY);
+  if(fd < 0){
+    printf("%s: open argvspan.out failed\n", s);
+    exit(1);
+  }
+  n = read(fd, out, sizeof(out));
+  close(fd);
+  unlink("argvspan.out");
+  if(n != 10 || memcmp(out, "a b c d e\n", 10) != 0){
+    printf("%s: echo printed the wrong arguments\n", s);
+    exit(1);
+  }
+}
+
+// exec fetches argv pointers a page at a time: check an array
+// that ends exactly at the end of the address space, and one
+// that spans a page boundary.
+void
+argvspan(char *s)
+{
+  char *strs[] = { "echo", "a", "b", "c", "d", "e", 0 };
+  char *cur, *boundary;
+  char **args;
+
+  cur = sbrk(0);
+  boundary = (char*)PGROUNDUP((uint64)cur) + PGSIZE;
+  if(sbrk(boundary - cur) == (char*)-1){
+    printf("%s: sbrk failed\n", s);
+    exit(1);
+  }
+
+  args = (char**)boundary - 7;
+  memmove(args, strs, sizeof(strs));
+  echoargs(s, args);
+
+  if(sbrk(PGSIZE) == (char*)-1){
+    printf("%s: sbrk failed\n", s);
+    exit(1);
+  }
+  args = (char**)boundary - 3;
+  memmove(args, strs, sizeof(strs));
+  echoargs(s, args);
+}
+
 // what happens when the file system runs out of blocks?
 // answer: balloc panics, so this test is not useful.
 void
@@ -2767,6 +4430,7 @@ main(int argc, char *argv[])
     char *s;
   } tests[] = {
     {manywrites, "manywrites"},
+    {diskrw, "diskrw"},
     {execout, "execout"},
     {copyin, "copyin"},
     {copyout, "copyout"},
@@ -2777,6 +4441,7 @@ main(int argc, char *argv[])
     {truncate1, "truncate1"},
     {truncate2, "truncate2"},
     {truncate3, "truncate3"},
+    {writecombine, "writecombine"},
     {reparent2, "reparent2"},
     {pgbug, "pgbug" },
     {sbrkbugs, "sbrkbugs" },
@@ -2790,19 +4455,27 @@ main(int argc, char *argv[])
     {createdelete, "createdelete"},
     {linkunlink, "linkunlink"},
     {linktest, "linktest"},
+    {linkfail, "linkfail"},
     {unlinkread, "unlinkread"},
     {concreate, "concreate"},
     {subdir, "subdir"},
+    {rmdirtest, "rmdirtest"},
+    {dirholes, "dirholes"},
     {fourfiles, "fourfiles"},
     {sharedfd, "sharedfd"},
     {dirtest, "dirtest"},
     {exectest, "exectest"},
     {bigargtest, "bigargtest"},
+    {argmaxtest, "argmaxtest"},
+    {manyargs, "manyargs"},
+    {argvspan, "argvspan"},
+    {envtest, "envtest"},
     {bigwrite, "bigwrite"},
     {bsstest, "bsstest"},
     {sbrkbasic, "sbrkbasic"},
     {sbrkmuch, "sbrkmuch"},
     {kernmem, "kernmem"},
+    {mprotecttest, "mprotecttest"},
     {sbrkfail, "sbrkfail"},
     {sbrkarg, "sbrkarg"},
     {validatetest, "validatetest"},
@@ -2816,6 +4489,7 @@ main(int argc, char *argv[])
     {iputtest, "iput"},
     {mem, "mem"},
     {pipe1, "pipe1"},
+    {pipehangup, "pipehangup"},
     {killstatus, "killstatus"},
     {preempt, "preempt"},
     {exitwait, "exitwait"},
@@ -2825,6 +4499,24 @@ main(int argc, char *argv[])
     {dirfile, "dirfile"},
     {iref, "iref"},
     {forktest, "forktest"},
+    {rusagetest, "rusagetest"},
+    {rlimittest, "rlimittest"},
+    {prlimittest, "prlimittest"},
+    {affinitytest, "affinitytest"},
+    {statfstest, "statfstest"},
+    {fscktest, "fscktest"},
+    {rdevtest, "rdevtest"},
+    {fifotest, "fifotest"},
+    {fifononblock, "fifononblock"},
+    {manyfds, "manyfds"},
+    {closerange, "closerange"},
+    {splicetest, "splicetest"},
+    {chroottest, "chroottest"},
+    {unlinkfree, "unlinkfree"},
+    {disktracetest, "disktracetest"},
+    {diskidtest, "diskidtest"},
+    {dmesgtest, "dmesgtest"},
+    {sleeptimer, "sleeptimer"},
     {bigdir, "bigdir"}, // slow
     { 0, 0},
   };
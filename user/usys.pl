@@ -37,3 +37,21 @@ entry("sbrk");
 entry("sleep");
 entry("uptime");
 entry("poweroff");
+entry("getrusage");
+entry("getrlimit");
+entry("setrlimit");
+entry("statfs");
+entry("execve");
+entry("rmdir");
+entry("mkfifo");
+entry("chroot");
+entry("reboot");
+entry("mprotect");
+entry("close_range");
+entry("prlimit");
+entry("sched_setaffinity");
+entry("sched_getaffinity");
+entry("getcpu");
+entry("splice");
+entry("fsck");
+entry("dmesg");